@@ -1,16 +1,23 @@
 #[cfg(unix)]
 use std::os::unix::process::CommandExt;
-use std::{env, ffi::CStr, path::PathBuf, process::Command};
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{CStr, CString, OsStr, OsString},
+    path::PathBuf,
+    process::Command,
+};
 
 use anyhow::{Ok, Result};
 #[cfg(unix)]
 use getopts::Options;
-use rustix::thread::{Gid, Uid, set_thread_res_gid, set_thread_res_uid};
+use rustix::thread::{Gid, Uid, set_thread_groups, set_thread_res_gid, set_thread_res_uid};
 
 #[allow(unused_imports)]
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use crate::pty::prepare_pty;
+use crate::pty::{SessionInfo, prepare_pty, session_recording_enabled};
 use crate::{
+    app_profile::{self, NamespaceMode},
     defs,
     utils::{self, umask},
 };
@@ -20,12 +27,56 @@ fn print_usage(opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
-fn set_identity(uid: u32, gid: u32) {
+fn set_identity(uid: u32, gid: u32, groups: &[u32]) -> std::io::Result<()> {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     let gid = Gid::from_raw(gid);
     let uid = Uid::from_raw(uid);
-    set_thread_res_gid(gid, gid, gid).ok();
-    set_thread_res_uid(uid, uid, uid).ok();
+    if !groups.is_empty() {
+        let groups: Vec<Gid> = groups.iter().map(|g| Gid::from_raw(*g)).collect();
+        // running without the requested groups could silently grant or deny access
+        set_thread_groups(&groups)?;
+    }
+    set_thread_res_gid(gid, gid, gid)?;
+    set_thread_res_uid(uid, uid, uid)?;
+    Result::Ok(())
+}
+
+// https://github.com/topjohnwu/Magisk/blob/master/native/src/core/su/su_daemon.cpp
+// the context is applied on the next execve, like setexeccon(3)
+fn set_exec_context(context: &str) -> std::io::Result<()> {
+    std::fs::write("/proc/thread-self/attr/exec", context)
+}
+
+fn lookup_uid(name: &str) -> Option<u32> {
+    let pw = CString::new(name)
+        .ok()
+        .and_then(|name| unsafe { libc::getpwnam(name.as_ptr()).as_ref() });
+    match pw {
+        Some(pw) => Some(pw.pw_uid),
+        None => name.parse::<u32>().ok(),
+    }
+}
+
+fn lookup_gid(name: &str) -> Option<u32> {
+    if let Result::Ok(gid) = name.parse::<u32>() {
+        return Some(gid);
+    }
+    let name = CString::new(name).ok()?;
+    unsafe { libc::getgrnam(name.as_ptr()).as_ref() }.map(|gr| gr.gr_gid)
+}
+
+fn parse_uid_or_exit(name: &str) -> u32 {
+    lookup_uid(name).unwrap_or_else(|| {
+        println!("su: unknown user: {name}");
+        std::process::exit(-1);
+    })
+}
+
+fn parse_gid_or_exit(name: &str) -> u32 {
+    lookup_gid(name).unwrap_or_else(|| {
+        println!("su: unknown group: {name}");
+        std::process::exit(-1);
+    })
 }
 
 #[cfg(not(unix))]
@@ -59,6 +110,22 @@ pub fn root_shell() -> Result<()> {
         "COMMAND",
     );
     opts.optflag("h", "help", "display this help message and exit");
+    opts.optflag(
+        "i",
+        "interactive",
+        "force pseudo-terminal allocation when using -c",
+    );
+    opts.optopt("g", "group", "specify the primary group", "GROUP");
+    opts.optmulti(
+        "G",
+        "supplementary-groups",
+        "specify a supplementary group, the first one is also used as the primary group if -g is not specified",
+        "GROUP",
+    );
+    opts.optopt("Z", "context", "change SELinux context", "CONTEXT");
+    opts.optopt("t", "target", "PID to take mount namespace from", "PID");
+    // legacy Magisk option, accepted and ignored
+    opts.optopt("z", "", "", "CONTEXT");
     opts.optflag("l", "login", "pretend the shell to be a login shell");
     opts.optflag(
         "p",
@@ -80,14 +147,16 @@ pub fn root_shell() -> Result<()> {
     );
     opts.optflag("", "no-pty", "Do not allocate a new pseudo terminal.");
 
-    // Replace -cn with -z, -mm with -M for supporting getopt_long
+    // Replace -cn with -Z, -mm with -M, -m with -p for supporting getopt_long
     let args = args
         .into_iter()
         .map(|e| {
             if e == "-mm" {
                 "-M".to_string()
+            } else if e == "-m" {
+                "-p".to_string()
             } else if e == "-cn" {
-                "-z".to_string()
+                "-Z".to_string()
             } else {
                 e
             }
@@ -118,10 +187,37 @@ pub fn root_shell() -> Result<()> {
         return Ok(());
     }
 
-    let shell = matches.opt_str("s").unwrap_or("/system/bin/sh".to_string());
+    let caller_uid = app_profile::caller_uid();
+    let profile = caller_uid
+        .map(|caller_uid| {
+            app_profile::profile_for_uid(caller_uid).unwrap_or_else(|e| {
+                log::warn!("failed to load root profile of uid {caller_uid}: {e:?}");
+                None
+            })
+        })
+        .unwrap_or_default()
+        .unwrap_or_default();
+    let capabilities = profile.capability_set().unwrap_or_else(|e| {
+        println!("su: {e}");
+        std::process::exit(-1);
+    });
+
+    let shell = matches
+        .opt_str("s")
+        .or_else(|| profile.shell.clone())
+        .unwrap_or("/system/bin/sh".to_string());
     let mut is_login = matches.opt_present("l");
     let preserve_env = matches.opt_present("p");
     let mount_master = matches.opt_present("M");
+    #[cfg(target_os = "android")]
+    let interactive = matches.opt_present("i") || !matches.opt_present("c");
+    let context = matches.opt_str("Z");
+    let target_pid = matches.opt_str("t").map(|pid| {
+        pid.parse::<i32>().unwrap_or_else(|_| {
+            println!("su: invalid target pid: {pid}");
+            std::process::exit(-1);
+        })
+    });
 
     // we've made sure that -c is the last option and it already contains the whole command, no need to construct it again
     let args = matches
@@ -137,59 +233,71 @@ pub fn root_shell() -> Result<()> {
 
     // use current uid if no user specified, these has been done in kernel!
     let mut uid = unsafe { libc::getuid() };
-    let gid = unsafe { libc::getgid() };
     if free_idx < matches.free.len() {
-        let name = &matches.free[free_idx];
-        uid = unsafe {
-            #[cfg(target_arch = "aarch64")]
-            let pw = libc::getpwnam(name.as_ptr()).as_ref();
-            #[cfg(target_arch = "x86_64")]
-            let pw = libc::getpwnam(name.as_ptr() as *const i8).as_ref();
-
-            match pw {
-                Some(pw) => pw.pw_uid,
-                None => name.parse::<u32>().unwrap_or(0),
-            }
-        }
+        uid = parse_uid_or_exit(&matches.free[free_idx]);
     }
 
+    // the first supplementary group is the primary group if -g is not specified
+    // options given on the command line take precedence over the root profile
+    let cli_groups: Vec<u32> = matches
+        .opt_strs("G")
+        .iter()
+        .map(|name| parse_gid_or_exit(name))
+        .collect();
+    let gid = matches
+        .opt_str("g")
+        .map(|name| parse_gid_or_exit(&name))
+        .or_else(|| cli_groups.first().copied())
+        .or(profile.gid)
+        .or_else(|| profile.groups.as_ref()?.first().copied())
+        .unwrap_or_else(|| unsafe { libc::getgid() });
+    let groups = if cli_groups.is_empty() {
+        profile.groups.clone().unwrap_or_default()
+    } else {
+        cli_groups
+    };
+    let namespace = if target_pid.is_some() || mount_master {
+        None
+    } else {
+        profile.namespace
+    };
+
     // https://github.com/topjohnwu/Magisk/blob/master/native/src/core/su/su_daemon.cpp#L408
     let arg0 = if is_login { "-" } else { &shell };
 
     let mut command = &mut Command::new(&shell);
 
-    if !preserve_env {
-        // This is actually incorrect, I don't know why.
-        // command = command.env_clear();
-
-        let pw = unsafe { libc::getpwuid(uid).as_ref() };
-
-        if let Some(pw) = pw {
-            let home = unsafe { CStr::from_ptr(pw.pw_dir) };
-            let pw_name = unsafe { CStr::from_ptr(pw.pw_name) };
-
-            let home = home.to_string_lossy();
-            let pw_name = pw_name.to_string_lossy();
-
-            command = command
-                .env("HOME", home.as_ref() as &str)
-                .env("USER", pw_name.as_ref() as &str)
-                .env("LOGNAME", pw_name.as_ref() as &str)
-                .env("SHELL", &shell);
-        }
-    }
-
-    // add /data/adb/ap/bin to PATH
-    #[cfg(any(target_os = "linux", target_os = "android"))]
-    add_path_to_env(defs::BINARY_DIR)?;
+    let identity = unsafe { libc::getpwuid(uid).as_ref() }.map(|pw| {
+        let home = unsafe { CStr::from_ptr(pw.pw_dir) };
+        let pw_name = unsafe { CStr::from_ptr(pw.pw_name) };
+        (
+            home.to_string_lossy().into_owned(),
+            pw_name.to_string_lossy().into_owned(),
+        )
+    });
+    let envs = build_env(
+        env::vars_os(),
+        preserve_env,
+        &read_env_allowlist(),
+        identity
+            .as_ref()
+            .map(|(home, name)| (home.as_str(), name.as_str())),
+        &shell,
+        PathBuf::from(defs::AP_RC_PATH)
+            .exists()
+            .then_some(defs::AP_RC_PATH),
+    )?;
+    command = command.env_clear().envs(envs);
 
-    // when AP_RC_PATH exists and ENV is not set, set ENV to AP_RC_PATH
-    if PathBuf::from(defs::AP_RC_PATH).exists() && env::var("ENV").is_err() {
-        command = command.env("ENV", defs::AP_RC_PATH);
-    }
     #[cfg(target_os = "android")]
-    if !matches.opt_present("no-pty") {
-        if let Err(e) = prepare_pty() {
+    if !matches.opt_present("no-pty") && interactive {
+        let session = session_recording_enabled().then(|| SessionInfo {
+            caller_uid: caller_uid.unwrap_or_default(),
+            target_uid: uid,
+            shell: shell.clone(),
+            command: matches.opt_str("c"),
+        });
+        if let Err(e) = prepare_pty(session) {
             log::error!("failed to prepare pty: {:?}", e);
         }
     }
@@ -205,11 +313,40 @@ pub fn root_shell() -> Result<()> {
             #[cfg(any(target_os = "linux", target_os = "android"))]
             let global_namespace_enable =
                 std::fs::read_to_string(defs::GLOBAL_NAMESPACE_FILE).unwrap_or("0".to_string());
-            if global_namespace_enable.trim() == "1" || mount_master {
-                let _ = utils::switch_mnt_ns(1);
+            match (target_pid, namespace) {
+                (Some(pid), _) => {
+                    // running in our own namespace instead would act on the wrong mounts
+                    utils::switch_mnt_ns(pid).map_err(|e| {
+                        std::io::Error::other(format!("cannot enter mount namespace of {pid}: {e}"))
+                    })?;
+                }
+                (None, Some(NamespaceMode::Global)) => {
+                    let _ = utils::switch_mnt_ns(1);
+                }
+                (None, Some(NamespaceMode::Private)) => {
+                    let _ = utils::unshare_mnt_ns();
+                }
+                (None, Some(NamespaceMode::Inherit)) => {}
+                (None, None) => {
+                    if global_namespace_enable.trim() == "1" || mount_master {
+                        let _ = utils::switch_mnt_ns(1);
+                    }
+                }
+            }
+
+            if let Some(context) = &context {
+                set_exec_context(context)?;
             }
 
-            set_identity(uid, gid);
+            if let Some(keep) = capabilities {
+                app_profile::restrict_capabilities(keep)?;
+            }
+
+            set_identity(uid, gid, &groups)?;
+
+            if let Some(keep) = capabilities {
+                app_profile::apply_capabilities(keep)?;
+            }
 
             Result::Ok(())
         })
@@ -219,12 +356,299 @@ pub fn root_shell() -> Result<()> {
     Err(command.exec().into())
 }
 
-fn add_path_to_env(path: &str) -> Result<()> {
-    let mut paths =
-        env::var_os("PATH").map_or(Vec::new(), |val| env::split_paths(&val).collect::<Vec<_>>());
-    let new_path = PathBuf::from(path.trim_end_matches('/'));
-    paths.push(new_path);
-    let new_path_env = env::join_paths(paths)?;
-    unsafe { env::set_var("PATH", new_path_env) };
-    Ok(())
+/// Variables kept from the caller when the environment is not preserved.
+/// A trailing `*` matches any variable with that prefix.
+const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "PATH",
+    "ANDROID_*",
+    "BOOTCLASSPATH",
+    "DEX2OATBOOTCLASSPATH",
+    "SYSTEMSERVERCLASSPATH",
+    "EXTERNAL_STORAGE",
+    "TERM",
+];
+
+/// Variables which are never inherited into a sanitized environment, even if allowlisted.
+const ENV_DENYLIST: &[&str] = &["LD_*", "IFS", "ENV", "BASH_ENV", "PS4"];
+
+fn env_pattern_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// Parse allowlist patterns, one per line, `#` starts a comment
+fn parse_env_allowlist(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn read_env_allowlist() -> Vec<String> {
+    std::fs::read_to_string(defs::SU_ENV_ALLOWLIST_PATH)
+        .map(|content| parse_env_allowlist(&content))
+        .unwrap_or_default()
+}
+
+/// Build the environment of the root shell.
+///
+/// With `preserve_env` the caller's environment is passed through untouched. Otherwise only
+/// variables matching [`DEFAULT_ENV_ALLOWLIST`] or `allowlist` survive, anything matching
+/// [`ENV_DENYLIST`] is dropped, and HOME/USER/LOGNAME/SHELL describe the target user.
+/// `rc_path` becomes ENV unless the environment already sets one.
+fn build_env(
+    caller_env: impl IntoIterator<Item = (OsString, OsString)>,
+    preserve_env: bool,
+    allowlist: &[String],
+    identity: Option<(&str, &str)>,
+    shell: &str,
+    rc_path: Option<&str>,
+) -> Result<BTreeMap<OsString, OsString>> {
+    let allowed = |name: &str| {
+        !ENV_DENYLIST
+            .iter()
+            .any(|pattern| env_pattern_matches(pattern, name))
+            && DEFAULT_ENV_ALLOWLIST
+                .iter()
+                .copied()
+                .chain(allowlist.iter().map(String::as_str))
+                .any(|pattern| env_pattern_matches(pattern, name))
+    };
+
+    let mut envs: BTreeMap<OsString, OsString> = caller_env
+        .into_iter()
+        .filter(|(name, _)| preserve_env || name.to_str().is_some_and(allowed))
+        .collect();
+
+    if !preserve_env && let Some((home, name)) = identity {
+        envs.insert("HOME".into(), home.into());
+        envs.insert("USER".into(), name.into());
+        envs.insert("LOGNAME".into(), name.into());
+        envs.insert("SHELL".into(), shell.into());
+    }
+
+    // add /data/adb/ap/bin to PATH
+    let mut paths = envs
+        .get(OsStr::new("PATH"))
+        .map_or(Vec::new(), |val| env::split_paths(val).collect::<Vec<_>>());
+    paths.push(PathBuf::from(defs::BINARY_DIR.trim_end_matches('/')));
+    envs.insert("PATH".into(), env::join_paths(paths)?);
+
+    // when AP_RC_PATH exists and ENV is not set, set ENV to AP_RC_PATH
+    if let Some(rc_path) = rc_path
+        && !envs.contains_key(OsStr::new("ENV"))
+    {
+        envs.insert("ENV".into(), rc_path.into());
+    }
+
+    Ok(envs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller_env(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(name, value)| (OsString::from(name), OsString::from(value)))
+            .collect()
+    }
+
+    fn get<'a>(envs: &'a BTreeMap<OsString, OsString>, name: &str) -> Option<&'a str> {
+        envs.get(OsStr::new(name)).and_then(|value| value.to_str())
+    }
+
+    fn bin_dir() -> &'static str {
+        defs::BINARY_DIR.trim_end_matches('/')
+    }
+
+    #[test]
+    fn default_allowlist_keeps_only_listed_variables() {
+        let envs = build_env(
+            caller_env(&[
+                ("TERM", "xterm"),
+                ("ANDROID_DATA", "/data"),
+                ("SECRET", "x"),
+                ("HOME", "/caller"),
+            ]),
+            false,
+            &[],
+            None,
+            "/system/bin/sh",
+            None,
+        )
+        .unwrap();
+        assert_eq!(get(&envs, "TERM"), Some("xterm"));
+        assert_eq!(get(&envs, "ANDROID_DATA"), Some("/data"));
+        assert_eq!(get(&envs, "SECRET"), None);
+        assert_eq!(get(&envs, "HOME"), None);
+    }
+
+    #[test]
+    fn child_receives_only_sanitized_env() {
+        let envs = build_env(
+            caller_env(&[
+                ("PATH", &env::var("PATH").unwrap_or_default()),
+                ("TERM", "xterm"),
+                ("TMPDIR", "/data/local/tmp/caller"),
+                ("LD_PRELOAD", "/data/local/tmp/evil.so"),
+                ("SECRET", "x"),
+            ]),
+            false,
+            &[],
+            Some(("/root", "root")),
+            "/system/bin/sh",
+            Some("/rc"),
+        )
+        .unwrap();
+        // spawned the same way root_shell execs the shell
+        let output = Command::new("env")
+            .env_clear()
+            .envs(&envs)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut received: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.split_once('=').unwrap().0.to_string())
+            .collect();
+        received.sort();
+        assert_eq!(
+            received,
+            ["ENV", "HOME", "LOGNAME", "PATH", "SHELL", "TERM", "USER"]
+        );
+    }
+
+    #[test]
+    fn allowlist_file_adds_patterns() {
+        let allowlist = parse_env_allowlist("# comment\n\n  LANG \nMY_*\n");
+        assert_eq!(allowlist, ["LANG", "MY_*"]);
+
+        let envs = build_env(
+            caller_env(&[("LANG", "C"), ("MY_VAR", "1"), ("OTHER", "2")]),
+            false,
+            &allowlist,
+            None,
+            "/system/bin/sh",
+            None,
+        )
+        .unwrap();
+        assert_eq!(get(&envs, "LANG"), Some("C"));
+        assert_eq!(get(&envs, "MY_VAR"), Some("1"));
+        assert_eq!(get(&envs, "OTHER"), None);
+    }
+
+    #[test]
+    fn denylist_wins_over_allowlist() {
+        let allowlist = parse_env_allowlist("LD_*\nIFS\nENV\nBASH_ENV\nPS4");
+        let envs = build_env(
+            caller_env(&[
+                ("LD_PRELOAD", "/data/local/tmp/evil.so"),
+                ("LD_LIBRARY_PATH", "/data/local/tmp"),
+                ("IFS", "/"),
+                ("ENV", "/data/local/tmp/rc"),
+                ("BASH_ENV", "/data/local/tmp/rc"),
+                ("PS4", "$(id)"),
+            ]),
+            false,
+            &allowlist,
+            None,
+            "/system/bin/sh",
+            None,
+        )
+        .unwrap();
+        for name in [
+            "LD_PRELOAD",
+            "LD_LIBRARY_PATH",
+            "IFS",
+            "ENV",
+            "BASH_ENV",
+            "PS4",
+        ] {
+            assert_eq!(get(&envs, name), None, "{name} was inherited");
+        }
+    }
+
+    #[test]
+    fn preserve_env_keeps_everything() {
+        let envs = build_env(
+            caller_env(&[
+                ("LD_PRELOAD", "lib.so"),
+                ("SECRET", "x"),
+                ("HOME", "/caller"),
+            ]),
+            true,
+            &[],
+            Some(("/root", "root")),
+            "/system/bin/sh",
+            None,
+        )
+        .unwrap();
+        assert_eq!(get(&envs, "LD_PRELOAD"), Some("lib.so"));
+        assert_eq!(get(&envs, "SECRET"), Some("x"));
+        assert_eq!(get(&envs, "HOME"), Some("/caller"));
+        assert_eq!(get(&envs, "USER"), None);
+    }
+
+    #[test]
+    fn identity_describes_target_user() {
+        let envs = build_env(
+            caller_env(&[("HOME", "/caller"), ("USER", "shell")]),
+            false,
+            &parse_env_allowlist("HOME\nUSER"),
+            Some(("/root", "root")),
+            "/bin/zsh",
+            None,
+        )
+        .unwrap();
+        assert_eq!(get(&envs, "HOME"), Some("/root"));
+        assert_eq!(get(&envs, "USER"), Some("root"));
+        assert_eq!(get(&envs, "LOGNAME"), Some("root"));
+        assert_eq!(get(&envs, "SHELL"), Some("/bin/zsh"));
+    }
+
+    #[test]
+    fn path_gets_binary_dir_appended() {
+        let envs = build_env(
+            caller_env(&[("PATH", "/system/bin")]),
+            false,
+            &[],
+            None,
+            "/system/bin/sh",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            get(&envs, "PATH").unwrap(),
+            format!("/system/bin:{}", bin_dir())
+        );
+
+        let envs = build_env(Vec::new(), false, &[], None, "/system/bin/sh", None).unwrap();
+        assert_eq!(get(&envs, "PATH"), Some(bin_dir()));
+    }
+
+    #[test]
+    fn rc_path_sets_env_unless_already_set() {
+        let envs = build_env(Vec::new(), false, &[], None, "/system/bin/sh", Some("/rc")).unwrap();
+        assert_eq!(get(&envs, "ENV"), Some("/rc"));
+
+        let envs = build_env(
+            caller_env(&[("ENV", "/caller/rc")]),
+            true,
+            &[],
+            None,
+            "/system/bin/sh",
+            Some("/rc"),
+        )
+        .unwrap();
+        assert_eq!(get(&envs, "ENV"), Some("/caller/rc"));
+
+        let envs = build_env(Vec::new(), false, &[], None, "/system/bin/sh", None).unwrap();
+        assert_eq!(get(&envs, "ENV"), None);
+    }
 }
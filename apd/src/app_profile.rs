@@ -0,0 +1,172 @@
+//! Per-app root profiles
+//!
+//! Profiles refine what `su` does for a granted app beyond the kernel grant (uid, to_uid and
+//! scontext in `package_config`): mount namespace, groups, kept capabilities and default shell.
+//! Templates can be shared by any number of packages, package entries override template fields.
+
+use std::{collections::HashMap, fs, io};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use rustix::thread::{
+    CapabilitySet, CapabilitySets, configure_capability_in_ambient_set,
+    remove_capability_from_bounding_set, set_capabilities, set_keep_capabilities,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{defs, package};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceMode {
+    /// Stay in the caller's mount namespace
+    Inherit,
+    /// Switch to the global mount namespace of init
+    Global,
+    /// Get a private copy of the caller's mount namespace
+    Private,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RootProfile {
+    pub namespace: Option<NamespaceMode>,
+    pub gid: Option<u32>,
+    pub groups: Option<Vec<u32>>,
+    /// Capability names such as `CAP_NET_ADMIN` or `net_admin`, all others are dropped
+    pub capabilities: Option<Vec<String>>,
+    pub shell: Option<String>,
+}
+
+impl RootProfile {
+    /// Fill unset fields from `base`
+    fn or(self, base: &Self) -> Self {
+        Self {
+            namespace: self.namespace.or(base.namespace),
+            gid: self.gid.or(base.gid),
+            groups: self.groups.or_else(|| base.groups.clone()),
+            capabilities: self.capabilities.or_else(|| base.capabilities.clone()),
+            shell: self.shell.or_else(|| base.shell.clone()),
+        }
+    }
+
+    pub fn capability_set(&self) -> Result<Option<CapabilitySet>> {
+        let Some(names) = &self.capabilities else {
+            return Ok(None);
+        };
+        let mut set = CapabilitySet::empty();
+        for name in names {
+            let upper = name.trim().to_ascii_uppercase();
+            let Some(cap) = CapabilitySet::from_name(upper.trim_start_matches("CAP_")) else {
+                bail!("Unknown capability: {name}");
+            };
+            set |= cap;
+        }
+        Ok(Some(set))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PackageProfile {
+    pub template: Option<String>,
+    #[serde(flatten)]
+    pub profile: RootProfile,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub templates: HashMap<String, RootProfile>,
+    pub packages: HashMap<String, PackageProfile>,
+}
+
+impl ProfileConfig {
+    pub fn load() -> Result<Self> {
+        let content = match fs::read_to_string(defs::SU_PROFILE_PATH) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).context("Failed to read su profiles"),
+        };
+        serde_json::from_str(&content).context("Failed to parse su profiles")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let temp_path = format!("{}.tmp", defs::SU_PROFILE_PATH);
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)
+            .context("Failed to write su profiles")?;
+        fs::rename(&temp_path, defs::SU_PROFILE_PATH).context("Failed to save su profiles")?;
+        Ok(())
+    }
+
+    /// Resolve the effective profile of a package, applying its template
+    pub fn resolve(&self, pkg: &str) -> Option<RootProfile> {
+        let package = self.packages.get(pkg)?;
+        let Some(template_name) = &package.template else {
+            return Some(package.profile.clone());
+        };
+        match self.templates.get(template_name) {
+            Some(template) => Some(package.profile.clone().or(template)),
+            None => {
+                warn!("Template {template_name} of {pkg} not found");
+                Some(package.profile.clone())
+            }
+        }
+    }
+}
+
+/// Get the real uid of the process which invoked su, the kernel has already made us root
+pub fn caller_uid() -> Option<u32> {
+    let ppid = std::os::unix::process::parent_id();
+    let status = fs::read_to_string(format!("/proc/{ppid}/status")).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().next())
+        .and_then(|uid| uid.parse().ok())
+}
+
+/// Find the profile for `uid`, matching on app id so that all users share a profile
+pub fn profile_for_uid(uid: u32) -> Result<Option<RootProfile>> {
+    let config = ProfileConfig::load()?;
+    if config.packages.is_empty() {
+        return Ok(None);
+    }
+
+    let packages = package::read_packages_list().context("Failed to read packages.list")?;
+    Ok(packages
+        .iter()
+        .filter(|record| config.packages.contains_key(&record.pkg))
+        .find(|record| record.uid as u32 % 100000 == uid % 100000)
+        .and_then(|record| config.resolve(&record.pkg)))
+}
+
+/// Drop every capability not in `keep` from the bounding set and keep permitted capabilities
+/// across the following uid change. Must run before switching identity.
+pub fn restrict_capabilities(keep: CapabilitySet) -> io::Result<()> {
+    set_keep_capabilities(true)?;
+    for cap in CapabilitySet::all().iter() {
+        if !keep.contains(cap) {
+            // capabilities unknown to the running kernel fail with EINVAL
+            let _ = remove_capability_from_bounding_set(cap);
+        }
+    }
+    Ok(())
+}
+
+/// Reduce the current sets to `keep` and raise them into the ambient set, so that they
+/// survive execve for non-root target uids. Must run after switching identity.
+pub fn apply_capabilities(keep: CapabilitySet) -> io::Result<()> {
+    set_capabilities(
+        None,
+        CapabilitySets {
+            effective: keep,
+            permitted: keep,
+            inheritable: keep,
+        },
+    )?;
+    for cap in keep.iter() {
+        configure_capability_in_ambient_set(cap, true)?;
+    }
+    Ok(())
+}
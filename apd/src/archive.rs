@@ -0,0 +1,270 @@
+//! Checked extraction of untrusted module zips
+//!
+//! Every entry is validated before anything is written: paths must stay inside the destination,
+//! symlinks must point inside it, device files and fifos are refused and the archive has to fit
+//! the configured quotas. Sizes are enforced again while writing because the headers can lie.
+//! Nothing is ever written below a symlink, since lexical checks cannot tell where a chain of
+//! links physically leads.
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::defs;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Entries smaller than this are not subject to the expansion ratio check
+const RATIO_MIN_SIZE: u64 = 1024 * 1024;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct ExtractLimits {
+    /// Upper bound of the uncompressed size of all entries, in bytes
+    pub max_total_size: u64,
+    pub max_entries: usize,
+    /// Upper bound of uncompressed / compressed size, for the archive and for large entries
+    pub max_ratio: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        Self {
+            max_total_size: 2 * 1024 * 1024 * 1024,
+            max_entries: 65536,
+            max_ratio: 200,
+        }
+    }
+}
+
+impl ExtractLimits {
+    /// Load the quotas from the limits file, falling back to the defaults
+    pub fn load() -> Self {
+        match fs::read_to_string(defs::EXTRACT_LIMITS_PATH) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Failed to parse extract limits, using defaults: {e}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+fn reject<T>(name: &str, reason: impl std::fmt::Display) -> Result<T> {
+    bail!("Rejected zip entry '{name}': {reason}")
+}
+
+/// Resolve `target` of a symlink at `link` lexically, `None` if it leaves the root
+fn resolve_inside(link: &Path, target: &Path) -> Option<PathBuf> {
+    if target.is_absolute() {
+        return None;
+    }
+    let mut resolved = PathBuf::new();
+    let parent = link.parent().unwrap_or(Path::new(""));
+    for component in parent.components().chain(target.components()) {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+/// Validate all entry headers of `zip` against `limits` without extracting anything
+pub fn check_archive(zip: &Path, limits: &ExtractLimits) -> Result<()> {
+    let file = fs::File::open(zip).with_context(|| format!("Failed to open {}", zip.display()))?;
+    let mut archive = zip::ZipArchive::new(file)?;
+
+    if archive.len() > limits.max_entries {
+        bail!(
+            "Archive has {} entries, the limit is {}",
+            archive.len(),
+            limits.max_entries
+        );
+    }
+
+    let mut symlinks = HashSet::new();
+    let mut paths = Vec::with_capacity(archive.len());
+    let mut total_size: u64 = 0;
+    let mut total_compressed: u64 = 0;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = entry.name().to_string();
+        let Some(path) = entry.enclosed_name() else {
+            return reject(&name, "absolute path or path traversal");
+        };
+
+        let file_type = entry.unix_mode().map_or(0, |mode| mode & S_IFMT);
+        if !matches!(file_type, 0 | S_IFREG | S_IFDIR | S_IFLNK) {
+            return reject(&name, "device files, fifos and sockets are not allowed");
+        }
+
+        if entry.is_symlink() {
+            let mut target = String::new();
+            entry
+                .by_ref()
+                .take(libc::PATH_MAX as u64)
+                .read_to_string(&mut target)
+                .or_else(|e| reject(&name, format!("unreadable symlink target: {e}")))?;
+            if resolve_inside(&path, Path::new(&target)).is_none() {
+                return reject(&name, format!("symlink to '{target}' escapes the module"));
+            }
+            symlinks.insert(path.clone());
+        }
+
+        let size = entry.size();
+        let compressed = entry.compressed_size();
+        if size >= RATIO_MIN_SIZE && size / compressed.max(1) > limits.max_ratio {
+            return reject(
+                &name,
+                format!("expansion ratio exceeds {}", limits.max_ratio),
+            );
+        }
+        total_size = total_size.saturating_add(size);
+        total_compressed = total_compressed.saturating_add(compressed);
+        if total_size > limits.max_total_size {
+            return reject(
+                &name,
+                format!(
+                    "archive exceeds {} bytes uncompressed",
+                    limits.max_total_size
+                ),
+            );
+        }
+        paths.push((name, path));
+    }
+
+    if total_size >= RATIO_MIN_SIZE && total_size / total_compressed.max(1) > limits.max_ratio {
+        bail!(
+            "Archive expansion ratio exceeds {}, refusing to extract",
+            limits.max_ratio
+        );
+    }
+
+    for (name, path) in &paths {
+        if path
+            .ancestors()
+            .skip(1)
+            .any(|parent| symlinks.contains(parent))
+        {
+            return reject(name, "path goes through a symlink");
+        }
+    }
+    Ok(())
+}
+
+/// Refuse to write `path` below `dest` if anything on the way is a symlink
+fn ensure_no_symlink_ancestor(dest: &Path, path: &Path, name: &str) -> Result<()> {
+    let mut current = dest.to_path_buf();
+    for component in path.parent().unwrap_or(Path::new("")).components() {
+        current.push(component);
+        if fs::symlink_metadata(&current).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            return reject(name, "path goes through a symlink");
+        }
+    }
+    Ok(())
+}
+
+/// Extract `zip` into `dest` after validating it with `check_archive`
+///
+/// Entries below the top level dirs in `exclude` are skipped.
+pub fn extract_archive(
+    zip: &Path,
+    dest: &Path,
+    limits: &ExtractLimits,
+    exclude: &[&str],
+) -> Result<()> {
+    check_archive(zip, limits)?;
+
+    let file = fs::File::open(zip).with_context(|| format!("Failed to open {}", zip.display()))?;
+    let mut archive = zip::ZipArchive::new(file)?;
+    fs::create_dir_all(dest)?;
+
+    let mut remaining = limits.max_total_size;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = entry.name().to_string();
+        let Some(path) = entry.enclosed_name() else {
+            return reject(&name, "absolute path or path traversal");
+        };
+        if path
+            .components()
+            .next()
+            .is_some_and(|first| exclude.iter().any(|dir| first.as_os_str() == *dir))
+        {
+            continue;
+        }
+        let out_path = dest.join(&path);
+        ensure_no_symlink_ancestor(dest, &path, &name)?;
+
+        if entry.is_dir() {
+            if out_path.is_symlink() {
+                return reject(&name, "directory replaces a symlink");
+            }
+            fs::create_dir_all(&out_path)?;
+            continue;
+        }
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // never write through a symlink created by an earlier entry
+        if out_path.is_symlink() {
+            fs::remove_file(&out_path)?;
+        }
+
+        if entry.is_symlink() {
+            let mut target = String::new();
+            entry
+                .by_ref()
+                .take(libc::PATH_MAX as u64)
+                .read_to_string(&mut target)?;
+            std::os::unix::fs::symlink(&target, &out_path)
+                .with_context(|| format!("Failed to create symlink {}", out_path.display()))?;
+            continue;
+        }
+
+        let declared = entry.size();
+        let mode = entry.unix_mode();
+        let mut out = fs::File::create(&out_path)
+            .with_context(|| format!("Failed to create {}", out_path.display()))?;
+        let written = io::copy(
+            &mut entry.by_ref().take(declared.min(remaining) + 1),
+            &mut out,
+        )?;
+        if written > declared {
+            return reject(&name, "entry is larger than its header claims");
+        }
+        if written > remaining {
+            return reject(
+                &name,
+                format!(
+                    "archive exceeds {} bytes uncompressed",
+                    limits.max_total_size
+                ),
+            );
+        }
+        remaining -= written;
+
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&out_path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+    Ok(())
+}
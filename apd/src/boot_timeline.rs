@@ -0,0 +1,236 @@
+//! Boot timeline profiling
+//!
+//! Boot events run in separate apd processes, each collecting timed spans in memory and
+//! appending them to `boot_timeline/<boot_id>.json` under the working dir when the event is
+//! done. Only the last `MAX_KEPT_BOOTS` boots are kept, enough to compare against the previous
+//! boot.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{defs, utils::ensure_dir_exists};
+
+const MAX_KEPT_BOOTS: usize = 5;
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TimelineEntry {
+    pub name: String,
+    /// Milliseconds since the epoch
+    pub start_ms: u64,
+    /// `None` for scripts which were spawned without waiting for them
+    pub end_ms: Option<u64>,
+}
+
+impl TimelineEntry {
+    fn duration_ms(&self) -> Option<u64> {
+        self.end_ms.map(|end| end.saturating_sub(self.start_ms))
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+#[serde(default)]
+pub struct BootTimeline {
+    pub boot_id: String,
+    pub entries: Vec<TimelineEntry>,
+}
+
+impl BootTimeline {
+    fn started_at(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.start_ms)
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+static PENDING: Mutex<Vec<TimelineEntry>> = Mutex::new(Vec::new());
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn push(entry: TimelineEntry) {
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.push(entry);
+    }
+}
+
+/// A timed span, recorded when dropped
+pub struct Span {
+    name: String,
+    start_ms: u64,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        push(TimelineEntry {
+            name: std::mem::take(&mut self.name),
+            start_ms: self.start_ms,
+            end_ms: Some(now_ms()),
+        });
+    }
+}
+
+#[must_use]
+pub fn span(name: impl Into<String>) -> Span {
+    Span {
+        name: name.into(),
+        start_ms: now_ms(),
+    }
+}
+
+/// Record a script which was started in the background
+pub fn record_spawned(name: impl Into<String>) {
+    push(TimelineEntry {
+        name: name.into(),
+        start_ms: now_ms(),
+        end_ms: None,
+    });
+}
+
+fn current_boot_id() -> Result<String> {
+    Ok(fs::read_to_string(BOOT_ID_PATH)
+        .context("Failed to read boot id")?
+        .trim()
+        .to_string())
+}
+
+fn timeline_path(boot_id: &str) -> PathBuf {
+    Path::new(defs::BOOT_TIMELINE_DIR).join(format!("{boot_id}.json"))
+}
+
+/// Append the spans collected by this process to the timeline of the current boot
+pub fn flush() {
+    let entries = match PENDING.lock() {
+        Ok(mut pending) => std::mem::take(&mut *pending),
+        Err(_) => return,
+    };
+    if entries.is_empty() {
+        return;
+    }
+    if let Err(e) = append(entries) {
+        warn!("Failed to save boot timeline: {e}");
+    }
+}
+
+fn append(entries: Vec<TimelineEntry>) -> Result<()> {
+    ensure_dir_exists(defs::BOOT_TIMELINE_DIR)?;
+    let boot_id = current_boot_id()?;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(timeline_path(&boot_id))?;
+    // boot events may overlap, serialize the read-modify-write
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        bail!("Failed to lock boot timeline");
+    }
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut timeline: BootTimeline = serde_json::from_str(&content).unwrap_or_default();
+    timeline.boot_id = boot_id;
+    timeline.entries.extend(entries);
+
+    file.rewind()?;
+    file.set_len(0)?;
+    file.write_all(serde_json::to_string_pretty(&timeline)?.as_bytes())?;
+    drop(file);
+
+    prune_old_boots();
+    Ok(())
+}
+
+/// Saved boots, oldest first
+fn load_boots() -> Vec<BootTimeline> {
+    let Ok(dir) = fs::read_dir(defs::BOOT_TIMELINE_DIR) else {
+        return Vec::new();
+    };
+    let mut boots: Vec<BootTimeline> = dir
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| serde_json::from_str(&fs::read_to_string(path).ok()?).ok())
+        .collect();
+    boots.sort_by_key(BootTimeline::started_at);
+    boots
+}
+
+fn prune_old_boots() {
+    let boots = load_boots();
+    if boots.len() <= MAX_KEPT_BOOTS {
+        return;
+    }
+    for timeline in &boots[..boots.len() - MAX_KEPT_BOOTS] {
+        let _ = fs::remove_file(timeline_path(&timeline.boot_id));
+    }
+}
+
+/// Print the slowest items of the latest boot, compared against the boot before it
+pub fn print_report(count: usize) -> Result<()> {
+    let mut boots = load_boots();
+    let Some(latest) = boots.pop() else {
+        bail!("No boot timeline recorded yet");
+    };
+    let previous: HashMap<&str, u64> = boots
+        .last()
+        .map(|timeline| {
+            timeline
+                .entries
+                .iter()
+                .filter_map(|entry| Some((entry.name.as_str(), entry.duration_ms()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let total = latest
+        .entries
+        .iter()
+        .filter_map(|entry| entry.end_ms)
+        .max()
+        .unwrap_or_default()
+        .saturating_sub(latest.started_at());
+    println!("boot {}: {total}ms from post-fs-data", latest.boot_id);
+
+    let mut timed: Vec<(&TimelineEntry, u64)> = latest
+        .entries
+        .iter()
+        .filter_map(|entry| Some((entry, entry.duration_ms()?)))
+        .collect();
+    timed.sort_by_key(|(_, duration)| std::cmp::Reverse(*duration));
+    for (entry, duration) in timed.into_iter().take(count) {
+        let delta = match previous.get(entry.name.as_str()) {
+            Some(&before) => format!("{:+}ms", duration as i64 - before as i64),
+            None => "new".to_string(),
+        };
+        println!("{duration:>8}ms  {delta:>9}  {}", entry.name);
+    }
+
+    let spawned: Vec<&str> = latest
+        .entries
+        .iter()
+        .filter(|entry| entry.end_ms.is_none())
+        .map(|entry| entry.name.as_str())
+        .collect();
+    if !spawned.is_empty() {
+        println!("started in background: {}", spawned.join(", "));
+    }
+    Ok(())
+}
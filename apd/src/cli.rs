@@ -1,4 +1,7 @@
-use crate::{defs, event, lua, module, module_config, supercall, utils};
+use crate::{
+    boot_timeline, defs, event, lua, module, module_config, module_files, module_info,
+    module_journal, module_usage, package, package_policy, script_log, supercall, utils,
+};
 #[cfg(target_os = "android")]
 use android_logger::Config;
 use anyhow::{Context, Result};
@@ -45,11 +48,31 @@ enum Commands {
     /// Start uid listener for synchronizing root list
     UidListener,
 
+    /// Run the module scripts of an async `stage`, started by the stage event
+    #[command(hide = true)]
+    StageScripts { stage: String },
+
+    /// Copy stdin into the `stage` log of module `id`, started for each logged script
+    #[command(hide = true)]
+    ScriptLog { id: String, stage: String },
+
+    /// Superuser tools
+    Su {
+        #[command(subcommand)]
+        command: Su,
+    },
+
     /// Resetprop - Magisk-compatible system property tool
     Resetprop(crate::resetprop::Args),
 
     /// MagiskPolicy - SELinux Policy Patch Tool
     Sepolicy(crate::sepolicy::Args),
+
+    /// Boot diagnostics
+    Boot {
+        #[command(subcommand)]
+        command: Boot,
+    },
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -60,6 +83,25 @@ enum Module {
         zip: String,
     },
 
+    /// Check module <ZIP> without installing it
+    Verify {
+        /// module zip file path
+        zip: String,
+
+        /// check sepolicy.rule against this policy instead of the live one
+        #[arg(long)]
+        policy: Option<String>,
+    },
+
+    /// Extract module <ZIP> into <DEST> with the install checks, used by the installer script
+    #[command(hide = true)]
+    Extract {
+        /// module zip file path
+        zip: String,
+        /// destination dir
+        dest: String,
+    },
+
     /// Uninstall module <id>
     Uninstall {
         /// module id
@@ -89,6 +131,44 @@ enum Module {
         // module id
         id: String,
     },
+
+    /// show everything known about module <id> as JSON
+    Info {
+        // module id
+        id: String,
+    },
+
+    /// list the partition paths module <id> overlays
+    Files {
+        // module id
+        id: String,
+    },
+
+    /// show paths overlaid by more than one active module
+    Conflicts,
+
+    /// show disk usage of all modules
+    Du {
+        /// remove config dirs of modules which are gone
+        #[arg(long)]
+        clean: bool,
+    },
+
+    /// show the operation history of all modules or module <id>
+    History {
+        // module id
+        id: Option<String>,
+    },
+
+    /// show captured script logs of module <id>
+    Logs {
+        // module id
+        id: String,
+
+        /// only show the log of this stage, e.g. post-fs-data
+        #[arg(long)]
+        stage: Option<String>,
+    },
     /// module lua runner
     Lua {
         // module id
@@ -109,6 +189,45 @@ enum Module {
     },
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum Su {
+    /// List recorded root sessions, newest first
+    Sessions,
+
+    /// Replay a recorded root session <id>
+    Replay {
+        /// session id
+        id: String,
+        /// playback speed multiplier
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+
+    /// Print root and exclude decisions of all packages as JSON
+    Export,
+
+    /// Import root and exclude decisions from an exported <file>
+    Import {
+        /// exported JSON file
+        file: String,
+    },
+
+    /// Inspect the package policy for new apps
+    Policy {
+        #[command(subcommand)]
+        command: SuPolicy,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum SuPolicy {
+    /// Show which rule would fire for package <pkg>
+    Test {
+        /// package name
+        pkg: String,
+    },
+}
+
 #[derive(clap::Subcommand, Debug)]
 enum ModuleConfigCmd {
     /// Get a config value
@@ -152,11 +271,12 @@ enum ModuleConfigCmd {
 }
 
 #[derive(clap::Subcommand, Debug)]
-enum Sepolicy {
-    /// Check if sepolicy statement is supported/valid
-    Check {
-        /// sepolicy statements
-        sepolicy: String,
+enum Boot {
+    /// Show the slowest boot items, compared against the previous boot
+    Report {
+        /// number of items to show
+        #[arg(short, long, default_value_t = 10)]
+        count: usize,
     },
 }
 
@@ -200,22 +320,63 @@ pub fn run() -> Result<()> {
     }
 
     let result = match cli.command {
-        Commands::PostFsData => event::on_post_data_fs(cli.superkey),
+        Commands::PostFsData => {
+            let result = event::on_post_data_fs(cli.superkey);
+            boot_timeline::flush();
+            result
+        }
 
-        Commands::BootCompleted => event::on_boot_completed(cli.superkey),
+        Commands::BootCompleted => {
+            let result = event::on_boot_completed(cli.superkey);
+            boot_timeline::flush();
+            result
+        }
 
         Commands::UidListener => event::start_uid_listener(),
 
+        Commands::StageScripts { stage } => {
+            let result = module::run_async_stage(&stage);
+            boot_timeline::flush();
+            result
+        }
+
+        Commands::ScriptLog { id, stage } => script_log::pump_log(&id, &stage),
+
+        Commands::Su { command } => match command {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Su::Sessions => crate::pty::list_sessions(),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            Su::Sessions => anyhow::bail!("Session recording is not supported on this platform"),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Su::Replay { id, speed } => crate::pty::replay_session(&id, speed),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            Su::Replay { .. } => anyhow::bail!("Session replay is not supported on this platform"),
+            Su::Export => package::export_package_config(),
+            Su::Import { file } => package::import_package_config(&file, &cli.superkey),
+            Su::Policy { command } => match command {
+                SuPolicy::Test { pkg } => package_policy::test_package(&pkg),
+            },
+        },
+
         Commands::Module { command } => {
+            // verify only reads the zip, so it also works on a host without root
             #[cfg(any(target_os = "linux", target_os = "android"))]
-            {
+            if !matches!(command, Module::Verify { .. }) {
                 utils::switch_mnt_ns(1)?;
             }
             match command {
                 Module::Install { zip } => module::install_module(&zip),
+                Module::Verify { zip, policy } => module::verify_module(&zip, policy.as_deref()),
+                Module::Extract { zip, dest } => module::extract_module(&zip, &dest),
                 Module::Uninstall { id } => module::uninstall_module(&id),
                 Module::UndoUninstall { id } => module::undo_uninstall_module(&id),
                 Module::Action { id } => module::run_action(&id),
+                Module::Info { id } => module_info::print_info(&id),
+                Module::Files { id } => module_files::print_files(&id),
+                Module::Conflicts => module_files::print_conflicts(),
+                Module::Du { clean } => module_usage::print_usage(clean),
+                Module::History { id } => module_journal::print_history(id.as_deref()),
+                Module::Logs { id, stage } => script_log::print_logs(&id, stage.as_deref()),
                 Module::Lua { id, function } => {
                     lua::run_lua(&id, &function, false, true).map_err(|e| anyhow::anyhow!("{}", e))
                 }
@@ -314,7 +475,11 @@ pub fn run() -> Result<()> {
             }
         }
 
-        Commands::Services => event::on_services(cli.superkey),
+        Commands::Services => {
+            let result = event::on_services(cli.superkey);
+            boot_timeline::flush();
+            result
+        }
 
         Commands::Resetprop(resetprop_args) => crate::resetprop::execute(&resetprop_args)
             .inspect_err(|e| {
@@ -326,6 +491,10 @@ pub fn run() -> Result<()> {
             }),
 
         Commands::Sepolicy(sepolicy_args) => crate::sepolicy::execute(&sepolicy_args),
+
+        Commands::Boot { command } => match command {
+            Boot::Report { count } => boot_timeline::print_report(count),
+        },
     };
 
     if let Err(e) = &result {
@@ -7,14 +7,30 @@ pub const BINARY_DIR: &str = concatcp!(WORKING_DIR, "bin/");
 pub const APATCH_LOG_FOLDER: &str = concatcp!(WORKING_DIR, "log/");
 
 pub const AP_RC_PATH: &str = concatcp!(WORKING_DIR, ".aprc");
+pub const SU_ENV_ALLOWLIST_PATH: &str = concatcp!(WORKING_DIR, "su_env_allowlist");
 pub const KPMS_CONFIG: &str = concatcp!(KPMS_DIR, "config");
 pub const GLOBAL_NAMESPACE_FILE: &str = concatcp!(ADB_DIR, ".global_namespace_enable");
+pub const SU_PROFILE_PATH: &str = concatcp!(WORKING_DIR, "su_profiles.json");
+pub const PACKAGE_POLICY_PATH: &str = concatcp!(WORKING_DIR, "package_policy.json");
+pub const SU_RECORD_FILE: &str = concatcp!(WORKING_DIR, "su_record_enable");
 pub const DAEMON_PATH: &str = concatcp!(ADB_DIR, "apd");
 
 pub const MODULE_DIR: &str = concatcp!(ADB_DIR, "modules/");
 
 // warning: this directory should not change, or you need to change the code in module_installer.sh!!!
 pub const MODULE_UPDATE_DIR: &str = concatcp!(ADB_DIR, "modules_update/");
+pub const EXTRACT_LIMITS_PATH: &str = concatcp!(WORKING_DIR, "module_extract_limits.json");
+pub const MODULE_TRUSTED_KEYS_DIR: &str = concatcp!(WORKING_DIR, "trusted_keys/");
+pub const MODULE_SIGNATURE_POLICY_PATH: &str = concatcp!(WORKING_DIR, "module_signature_policy");
+pub const MODULE_STATUS_DIR: &str = concatcp!(WORKING_DIR, "module_status/");
+pub const SCRIPT_CONCURRENCY_PATH: &str = concatcp!(WORKING_DIR, "script_concurrency");
+pub const BOOT_ATTEMPTS_PATH: &str = concatcp!(WORKING_DIR, "boot_attempts");
+pub const LAST_GOOD_BOOT_PATH: &str = concatcp!(WORKING_DIR, "last_good_boot");
+pub const QUARANTINE_BISECT_PATH: &str = concatcp!(WORKING_DIR, "quarantine_bisect.json");
+pub const MODULE_JOURNAL_PATH: &str = concatcp!(WORKING_DIR, "module_journal.jsonl");
+pub const BOOT_TIMELINE_DIR: &str = concatcp!(WORKING_DIR, "boot_timeline/");
+pub const MODULE_INSTALL_BACKUP_DIR: &str = concatcp!(WORKING_DIR, "install_backup/");
+pub const MODULE_INSTALL_ZIP_DIR: &str = concatcp!(WORKING_DIR, "install_zip/");
 
 pub const TEMP_DIR: &str = "/debug_ramdisk";
 pub const TEMP_DIR_LEGACY: &str = "/sbin";
@@ -24,6 +40,9 @@ pub const MODULE_ACTION_SH: &str = "action.sh";
 pub const DISABLE_FILE_NAME: &str = "disable";
 pub const UPDATE_FILE_NAME: &str = "update";
 pub const REMOVE_FILE_NAME: &str = "remove";
+pub const SIGNER_FILE_NAME: &str = "signer";
+pub const INCOMPATIBLE_FILE_NAME: &str = "incompatible";
+pub const QUARANTINE_FILE_NAME: &str = "quarantine";
 
 // Metamodule support
 pub const METAMODULE_MOUNT_SCRIPT: &str = "metamount.sh";
@@ -37,6 +56,7 @@ pub const PERSIST_CONFIG_NAME: &str = "persist.config";
 pub const TEMP_CONFIG_NAME: &str = "tmp.config";
 
 pub const PTS_NAME: &str = "pts";
+pub const SU_SESSION_DIR_NAME: &str = "su_sessions";
 
 pub const VERSION_CODE: &str = include_str!(concat!(env!("OUT_DIR"), "/VERSION_CODE"));
 pub const VERSION_NAME: &str = include_str!(concat!(env!("OUT_DIR"), "/VERSION_NAME"));
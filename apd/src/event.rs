@@ -4,7 +4,8 @@ use crate::sepolicy::get_policy_main;
 use crate::supercall::init_load_su_path;
 use crate::supercall::refresh_ap_package_list;
 use crate::{
-    assets, defs, lua, metamodule, module, restorecon, supercall,
+    assets, boot_timeline, defs, lua, metamodule, module, module_compat, module_journal,
+    module_quarantine, restorecon, supercall,
     utils::{self, switch_cgroups},
 };
 use anyhow::{Context, Result};
@@ -38,6 +39,12 @@ pub fn report_kernel(superkey: Option<String>, event: &str, state: &str) -> Resu
 }
 
 pub fn on_post_data_fs(superkey: Option<String>) -> Result<()> {
+    let _span = boot_timeline::span("post-fs-data");
+    module_journal::set_source("boot");
+    // counted before anything below can fail, a boot loop caused there must be caught too
+    if let Err(e) = module_quarantine::on_boot_attempt() {
+        warn!("boot loop check failed: {}", e);
+    }
     utils::umask(0);
     report_kernel(superkey.clone(), "post-fs-data", "before")?;
     use std::process::Stdio;
@@ -71,8 +78,9 @@ pub fn on_post_data_fs(superkey: Option<String>) -> Result<()> {
         fs::set_permissions(defs::APATCH_LOG_FOLDER, permissions)
             .expect("Failed to set permissions");
     }
+    // only rotate plain log files, subdirectories (e.g. su sessions) keep their own history
     let command_string = format!(
-        "rm -rf {}*.old.log; for file in {}*; do mv \"$file\" \"$file.old.log\"; done",
+        "rm -rf {}*.old.log; for file in {}*; do if [ -f \"$file\" ]; then mv \"$file\" \"$file.old.log\"; fi; done",
         defs::APATCH_LOG_FOLDER,
         defs::APATCH_LOG_FOLDER
     );
@@ -177,6 +185,10 @@ pub fn on_post_data_fs(superkey: Option<String>) -> Result<()> {
         warn!("prune modules failed: {}", e);
     }
 
+    if let Err(e) = module_compat::disable_incompatible_modules() {
+        warn!("disable incompatible modules failed: {}", e);
+    }
+
     if let Err(e) = restorecon::restorecon() {
         warn!("restorecon failed: {}", e);
     }
@@ -189,8 +201,7 @@ pub fn on_post_data_fs(superkey: Option<String>) -> Result<()> {
         warn!("execute metamodule mount failed: {e}");
     }
 
-    // exec modules post-fs-data scripts
-    // TODO: Add timeout
+    // exec modules post-fs-data scripts, each one is killed when it runs out of time
     if let Err(e) = module::exec_stage_script("post-fs-data", true) {
         warn!("exec post-fs-data scripts failed: {}", e);
     }
@@ -213,6 +224,7 @@ pub fn on_post_data_fs(superkey: Option<String>) -> Result<()> {
 }
 
 fn run_stage(stage: &str, superkey: Option<String>, block: bool) {
+    let _span = boot_timeline::span(stage);
     utils::umask(0);
 
     if utils::has_magisk() {
@@ -281,6 +293,10 @@ fn run_uid_monitor() {
 pub fn on_boot_completed(superkey: Option<String>) -> Result<()> {
     info!("on_boot_completed triggered!");
 
+    if let Err(e) = module_quarantine::on_boot_completed() {
+        warn!("Failed to reset boot attempts: {e}");
+    }
+
     run_stage("boot-completed", superkey, false);
 
     run_uid_monitor();
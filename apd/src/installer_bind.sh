@@ -375,7 +375,8 @@ install_module() {
 
     if ! grep -q '^SKIPUNZIP=1$' $MODPATH/customize.sh 2>/dev/null; then
       ui_print "- Extracting module files"
-      unzip -o "$ZIPFILE" -x 'META-INF/*' -d $MODPATH >&2
+      # extracted by apd, which refuses paths escaping the module and oversized archives
+      $NVBASE/apd module extract "$ZIPFILE" "$MODPATH" >&2 || abort "! Unable to extract zip file!"
 
       # Default permissions
       set_perm_recursive $MODPATH 0 0 0755 0644
@@ -13,6 +13,7 @@ use log::warn;
 use rustix::path::Arg;
 
 use crate::{
+    boot_timeline,
     cli::SUPERCALL,
     defs::{KPMS_CONFIG, KPMS_DIR},
 };
@@ -73,6 +74,7 @@ fn kpm_list() -> Option<impl Iterator<Item = KpmInfo>> {
 }
 
 pub fn load_kpms(key: &CStr, stage: &str) -> Result<()> {
+    let _span = boot_timeline::span(format!("kpm {stage}"));
     let stage = stage_map(stage);
     if stage == u8::MAX {
         return Ok(());
@@ -18,7 +18,8 @@ pub fn load_text<P: AsRef<Path>>(filename: P) -> std::io::Result<String> {
     fs::read_to_string(path)
 }
 
-pub fn load_all_lua_modules(lua: &Lua) -> LuaResult<()> {
+/// Load the Lua files of all modules, returning the loaded ids in module execution order
+pub fn load_all_lua_modules(lua: &Lua) -> LuaResult<Vec<String>> {
     let modules_dir = Path::new("/data/adb/modules");
 
     let modules: Table = match lua.globals().get("modules") {
@@ -30,12 +31,9 @@ pub fn load_all_lua_modules(lua: &Lua) -> LuaResult<()> {
         }
     };
 
+    let mut loaded = Vec::new();
     if modules_dir.exists() {
-        for entry in fs::read_dir(modules_dir)
-            .unwrap_or_else(|_| fs::read_dir("/dev/null").unwrap())
-            .flatten()
-        {
-            let path = entry.path();
+        for path in sorted_module_dirs(modules_dir).unwrap_or_default() {
             if path.is_dir() {
                 let id = path.file_name().unwrap().to_string_lossy().to_string();
                 let package: Table = lua.globals().get("package")?;
@@ -55,6 +53,7 @@ pub fn load_all_lua_modules(lua: &Lua) -> LuaResult<()> {
                             {
                                 Ok(module) => {
                                     modules.set(id.clone(), module.clone())?;
+                                    loaded.push(id.clone());
                                 }
                                 Err(e) => {
                                     eprintln!("Failed to eval Lua {}: {}", lua_file.display(), e);
@@ -70,7 +69,7 @@ pub fn load_all_lua_modules(lua: &Lua) -> LuaResult<()> {
         }
     }
 
-    Ok(())
+    Ok(loaded)
 }
 
 pub fn info_lua(lua: &Lua) -> LuaResult<Function> {
@@ -89,6 +88,7 @@ pub fn warn_lua(lua: &Lua) -> LuaResult<Function> {
 
 pub fn install_module_lua(lua: &Lua) -> LuaResult<Function> {
     lua.create_function(|_, zip: String| {
+        crate::module_journal::set_source("lua");
         install_module(&zip)
             .map_err(|e| mlua::Error::external(format!("install_module failed: {}", e)))
     })
@@ -127,13 +127,14 @@ pub fn run_lua(id: &str, function: &str, on_each_module: bool, _wait: bool) -> m
     lua.globals().set("setConfig", save_text_lua(&lua)?)?;
     lua.globals().set("getConfig", read_text_lua(&lua)?)?;
 
-    load_all_lua_modules(&lua)?;
+    let loaded = load_all_lua_modules(&lua)?;
 
     let modules: mlua::Table = lua.globals().get("modules")?;
     if on_each_module {
-        for pair in modules.pairs::<String, mlua::Table>() {
-            let (_, module_table) = pair?;
+        for module_id in &loaded {
+            let module_table: mlua::Table = modules.get(module_id.as_str())?;
             if let Ok(func_obj) = module_table.get::<mlua::Function>(function) {
+                let _span = crate::boot_timeline::span(format!("lua {module_id}:{function}"));
                 func_obj.call::<()>(id)?;
             }
         }
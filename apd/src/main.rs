@@ -1,5 +1,8 @@
 mod apd;
+mod app_profile;
+mod archive;
 mod assets;
+mod boot_timeline;
 mod cli;
 mod defs;
 mod event;
@@ -7,12 +10,25 @@ mod kpm;
 mod lua;
 mod metamodule;
 mod module;
+mod module_compat;
 mod module_config;
+mod module_deps;
+mod module_files;
+mod module_info;
+mod module_journal;
+mod module_prop;
+mod module_quarantine;
+mod module_signature;
+mod module_status;
+mod module_transaction;
+mod module_usage;
 mod package;
+mod package_policy;
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod pty;
 mod resetprop;
 mod restorecon;
+mod script_log;
 mod sepolicy;
 mod supercall;
 mod utils;
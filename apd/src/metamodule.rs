@@ -5,23 +5,16 @@
 //! and provide hooks for module installation/uninstallation.
 
 use std::{
-    collections::HashMap,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, ExitStatus},
 };
 
 use anyhow::{Context, Result, ensure};
 use log::{info, warn};
 
-use crate::{assets, defs, module::ModuleType::All};
-
-/// Determine whether the provided module properties mark it as a metamodule
-pub fn is_metamodule(props: &HashMap<String, String>) -> bool {
-    props.get("metamodule").is_some_and(|s| {
-        let trimmed = s.trim();
-        trimmed == "1" || trimmed.eq_ignore_ascii_case("true")
-    })
-}
+use crate::{
+    assets, boot_timeline, defs, module::ModuleType::All, module_prop::Module, script_log,
+};
 
 /// Get metamodule path if it exists
 /// The metamodule is stored in /data/adb/modules/{id} with a symlink at /data/adb/metamodule
@@ -51,9 +44,7 @@ pub fn get_metamodule_path() -> Option<PathBuf> {
     // Fallback: search for metamodule=1 in modules directory
     let mut result = None;
     let _ = crate::module::foreach_module(All, |module_path| {
-        if let Ok(props) = crate::module::read_module_prop(module_path)
-            && is_metamodule(&props)
-        {
+        if Module::load(module_path).is_ok_and(|module| module.metamodule) {
             info!(
                 "Found metamodule in modules directory: {}",
                 module_path.display()
@@ -219,6 +210,20 @@ fn check_metamodule_script(script_name: &str) -> Option<PathBuf> {
     Some(script_path)
 }
 
+/// Run a metamodule script to completion, logging its output under the metamodule id
+fn run_logged(command: &mut Command, script: &Path) -> std::io::Result<ExitStatus> {
+    let id = script
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str())
+        .unwrap_or("metamodule");
+    let stage = script
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    script_log::status_logged(command, id, stage)
+}
+
 /// Execute metamodule's metauninstall.sh for a specific module
 pub fn exec_metauninstall_script(module_id: &str) -> Result<()> {
     let Some(metauninstall_path) = check_metamodule_script(defs::METAMODULE_METAUNINSTALL_SCRIPT)
@@ -228,12 +233,14 @@ pub fn exec_metauninstall_script(module_id: &str) -> Result<()> {
 
     info!("Executing metamodule metauninstall.sh for module: {module_id}",);
 
-    let result = Command::new(assets::BUSYBOX_PATH)
-        .args(["sh", metauninstall_path.to_str().unwrap()])
-        .current_dir(metauninstall_path.parent().unwrap())
-        .envs(crate::module::get_common_script_envs())
-        .env("MODULE_ID", module_id)
-        .status()?;
+    let result = run_logged(
+        Command::new(assets::BUSYBOX_PATH)
+            .args(["sh", metauninstall_path.to_str().unwrap()])
+            .current_dir(metauninstall_path.parent().unwrap())
+            .envs(crate::module::get_common_script_envs())
+            .env("MODULE_ID", module_id),
+        &metauninstall_path,
+    )?;
 
     ensure!(
         result.success(),
@@ -247,17 +254,20 @@ pub fn exec_metauninstall_script(module_id: &str) -> Result<()> {
 
 /// Execute metamodule mount script
 pub fn exec_mount_script(module_dir: &str) -> Result<()> {
+    let _span = boot_timeline::span("metamount");
     let Some(mount_script) = check_metamodule_script(defs::METAMODULE_MOUNT_SCRIPT) else {
         return Ok(());
     };
 
     info!("Executing mount script for metamodule");
 
-    let result = Command::new(assets::BUSYBOX_PATH)
-        .args(["sh", mount_script.to_str().unwrap()])
-        .envs(crate::module::get_common_script_envs())
-        .env("MODULE_DIR", module_dir)
-        .status()?;
+    let result = run_logged(
+        Command::new(assets::BUSYBOX_PATH)
+            .args(["sh", mount_script.to_str().unwrap()])
+            .envs(crate::module::get_common_script_envs())
+            .env("MODULE_DIR", module_dir),
+        &mount_script,
+    )?;
 
     ensure!(
         result.success(),
@@ -276,7 +286,11 @@ pub fn exec_stage_script(stage: &str, block: bool) -> Result<()> {
     };
 
     info!("Executing metamodule {stage}.sh");
-    crate::module::exec_script(&script_path, block)?;
+    crate::module::exec_script(
+        &script_path,
+        block,
+        crate::module::script_timeout(stage, None),
+    )?;
     info!("Metamodule {stage}.sh executed successfully");
     Ok(())
 }
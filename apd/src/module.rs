@@ -3,27 +3,35 @@ use crate::{lua, module_config};
 use anyhow::{Context, Result, anyhow, bail, ensure};
 use const_format::concatcp;
 use is_executable::is_executable;
-use java_properties::PropertiesIter;
 use log::{debug, info, warn};
 #[cfg(unix)]
-use std::os::unix::{prelude::PermissionsExt, process::CommandExt};
+use std::os::unix::{fs::OpenOptionsExt, prelude::PermissionsExt, process::CommandExt};
 use std::{
     collections::HashMap,
     env::var as env_var,
     fs::{self, remove_dir_all},
-    io::Cursor,
+    io::Read,
     path::{Path, PathBuf},
-    process::Command,
-    str::FromStr,
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant},
 };
-use zip_extensions::zip_extract_file_to_memory;
 
 #[allow(clippy::wildcard_imports)]
 use crate::utils::*;
 use crate::{
-    assets,
+    archive::{self, ExtractLimits},
+    assets, boot_timeline,
     defs::{self, MODULE_DIR, MODULE_UPDATE_DIR},
-    metamodule, restorecon,
+    metamodule, module_compat, module_deps,
+    module_journal::{self, JournalEntry},
+    module_prop::{ListEntry, Module},
+    module_quarantine,
+    module_signature::{self, SignatureStatus},
+    module_status::{self, ScriptResult},
+    module_transaction::InstallTransaction,
+    restorecon,
+    script_log::ScriptLog,
 };
 
 const INSTALLER_CONTENT: &str = include_str!("./installer.sh");
@@ -43,9 +51,9 @@ pub enum ModuleType {
     Updated,
 }
 
-fn exec_install_script(module_file: &str, is_metamodule: bool) -> Result<()> {
+fn exec_install_script(module_file: &Path, is_metamodule: bool) -> Result<()> {
     let realpath = std::fs::canonicalize(module_file)
-        .with_context(|| format!("realpath: {module_file} failed"))?;
+        .with_context(|| format!("realpath: {} failed", module_file.display()))?;
 
     // Get install script from metamodule module
     let install_script =
@@ -70,15 +78,22 @@ pub fn handle_updated_modules() -> Result<()> {
 
         if let Some(name) = updated_module.file_name() {
             let module_dir = modules_root.join(name);
-            let mut disabled = false;
-            let mut removed = false;
-            if module_dir.exists() {
-                // If the old module is disabled, we need to also disable the new one
-                disabled = module_dir.join(defs::DISABLE_FILE_NAME).exists();
-                removed = module_dir.join(defs::REMOVE_FILE_NAME).exists();
-                remove_dir_all(&module_dir)?;
+            let id = name.to_string_lossy();
+            let versions = JournalEntry::versions(
+                module_journal::version_code(&module_dir),
+                module_journal::version_code(updated_module),
+            );
+            // If the old module is disabled, we need to also disable the new one
+            let disabled = module_dir.join(defs::DISABLE_FILE_NAME).exists();
+            let removed = module_dir.join(defs::REMOVE_FILE_NAME).exists();
+            let result = if module_dir.exists() {
+                remove_dir_all(&module_dir)
+            } else {
+                Ok(())
             }
-            std::fs::rename(updated_module, &module_dir)?;
+            .and_then(|_| std::fs::rename(updated_module, &module_dir));
+            module_journal::record("apply_update", &id, versions, &result);
+            result?;
             if removed {
                 let path = module_dir.join(defs::REMOVE_FILE_NAME);
                 if let Err(e) = ensure_file_exists(&path) {
@@ -141,6 +156,30 @@ fn mark_module_state(module: &str, flag_file: &str, create_or_delete: bool) -> R
         Ok(())
     }
 }
+/// Priority of a module from `priority=` in module.prop, 0 if unset
+pub fn module_priority(module_path: &Path) -> i64 {
+    Module::load(module_path)
+        .map(|module| module.priority)
+        .unwrap_or_default()
+}
+
+/// Entries of `dir` in module execution order, by ascending priority and then by id
+pub fn sorted_module_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut modules: Vec<(i64, PathBuf)> = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| {
+            let path = entry.path();
+            (module_priority(&path), path)
+        })
+        .collect();
+    modules.sort_by(|(a_priority, a), (b_priority, b)| {
+        a_priority
+            .cmp(b_priority)
+            .then_with(|| a.file_name().cmp(&b.file_name()))
+    });
+    Ok(modules.into_iter().map(|(_, path)| path).collect())
+}
+
 pub fn foreach_module(
     module_type: ModuleType,
     mut f: impl FnMut(&Path) -> Result<()>,
@@ -149,9 +188,7 @@ pub fn foreach_module(
         ModuleType::Updated => MODULE_UPDATE_DIR,
         _ => defs::MODULE_DIR,
     });
-    let dir = std::fs::read_dir(modules_dir)?;
-    for entry in dir.flatten() {
-        let path = entry.path();
+    for path in sorted_module_dirs(modules_dir)? {
         if !path.is_dir() {
             warn!("{} is not a directory, skip", path.display());
             continue;
@@ -177,6 +214,7 @@ fn foreach_active_module(f: impl FnMut(&Path) -> Result<()>) -> Result<()> {
 }
 
 pub fn load_sepolicy_rule() -> Result<()> {
+    let _span = boot_timeline::span("sepolicy.rule");
     foreach_active_module(|path| {
         let rule_file = path.join("sepolicy.rule");
         if !rule_file.exists() {
@@ -197,7 +235,56 @@ pub fn load_sepolicy_rule() -> Result<()> {
     Ok(())
 }
 
-pub fn exec_script<T: AsRef<Path>>(path: T, wait: bool) -> Result<()> {
+const DEFAULT_SCRIPT_CONCURRENCY: usize = 4;
+
+/// Default time budget of a script of `stage`, async stages stop counting it against the
+/// concurrency cap instead of killing it
+fn default_stage_timeout(stage: &str) -> Option<Duration> {
+    match stage {
+        "post-fs-data" | "post-mount" => Some(Duration::from_secs(30)),
+        "service" | "boot-completed" => Some(Duration::from_secs(60)),
+        _ => None,
+    }
+}
+
+/// Time budget of the `stage` script of a module, `timeout=<seconds>` in module.prop overrides
+/// the stage default and `timeout=0` removes the limit
+pub fn script_timeout(stage: &str, module: Option<&Path>) -> Option<Duration> {
+    let timeout = module
+        .and_then(|module| Module::load(module).ok())
+        .and_then(|module| module.prop("timeout")?.trim().parse::<u64>().ok());
+    match timeout {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => default_stage_timeout(stage),
+    }
+}
+
+/// Wait for `child`, killing its whole process group once `timeout` expires
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> std::io::Result<(ExitStatus, bool)> {
+    let Some(timeout) = timeout else {
+        return Ok((child.wait()?, false));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            // scripts run in their own process group, take their children down with them
+            unsafe {
+                libc::kill(-(child.id() as i32), libc::SIGKILL);
+            }
+            return Ok((child.wait()?, true));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub fn exec_script<T: AsRef<Path>>(path: T, wait: bool, timeout: Option<Duration>) -> Result<()> {
     info!("exec {}", path.as_ref().display());
 
     let is_module_script = path.as_ref().starts_with(defs::MODULE_DIR);
@@ -250,31 +337,197 @@ pub fn exec_script<T: AsRef<Path>>(path: T, wait: bool) -> Result<()> {
         );
 
     // Set AP_MODULE environment variable
-    if let Some(id) = module_id {
+    if let Some(id) = &module_id {
         command = command.env("AP_MODULE", id);
     }
 
-    let result = if wait {
-        command.status().map(|_| ())
-    } else {
-        command.spawn().map(|_| ())
-    };
-    result.map_err(|err| anyhow!("Failed to exec {}: {}", path.as_ref().display(), err))
+    let stage = path
+        .as_ref()
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let mut log = module_id.as_deref().and_then(|id| {
+        ScriptLog::open(id, stage, wait)
+            .inspect_err(|e| warn!("Failed to open {stage} log of {id}: {e}"))
+            .ok()
+    });
+
+    if !wait {
+        if let Some(log) = &log {
+            log.redirect(command)?;
+        }
+        boot_timeline::record_spawned(path.as_ref().display().to_string());
+        return command
+            .spawn()
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to exec {}: {}", path.as_ref().display(), err));
+    }
+
+    // action.sh runs on behalf of the manager, which shows its output live
+    let tee = stage == "action";
+    let _span = boot_timeline::span(path.as_ref().display().to_string());
+    let start = Instant::now();
+    let mut pumps = Vec::new();
+    let mut child = match &log {
+        Some(log) if tee => log.spawn_tee(command).map(|(child, spawned)| {
+            pumps = spawned;
+            child
+        }),
+        Some(log) => log.redirect(command).and_then(|_| command.spawn()),
+        None => command.spawn(),
+    }
+    .map_err(|err| {
+        // the status store shows the script never ran, not an old result
+        if let Some(id) = &module_id {
+            module_status::record_script(id, stage, ScriptResult::failed_to_start(err.to_string()));
+        }
+        anyhow!("Failed to exec {}: {}", path.as_ref().display(), err)
+    })?;
+    let (status, timed_out) = wait_with_timeout(&mut child, timeout)
+        .map_err(|err| anyhow!("Failed to wait for {}: {}", path.as_ref().display(), err))?;
+    for pump in pumps {
+        let _ = pump.join();
+    }
+    if timed_out {
+        warn!(
+            "{} timed out after {:?}, killed its process group",
+            path.as_ref().display(),
+            timeout.unwrap_or_default()
+        );
+    }
+
+    if let Some(log) = &mut log {
+        log.finish(&status, timed_out, start.elapsed());
+    }
+    if let Some(id) = &module_id {
+        module_status::record_script(
+            id,
+            stage,
+            ScriptResult::new(status, timed_out, start.elapsed()),
+        );
+    }
+    Ok(())
 }
 
-pub fn exec_stage_script(stage: &str, block: bool) -> Result<()> {
+/// Maximum number of stage scripts running at once, set by the `script_concurrency` file
+fn script_concurrency() -> usize {
+    fs::read_to_string(defs::SCRIPT_CONCURRENCY_PATH)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&value| value > 0)
+        .unwrap_or(DEFAULT_SCRIPT_CONCURRENCY)
+}
+
+/// Whether module.prop opts into running post-fs-data.sh alongside other modules
+fn runs_in_parallel(module: &Path) -> bool {
+    Module::load(module).is_ok_and(|module| {
+        module
+            .prop("parallel")
+            .is_some_and(|value| matches!(value.trim(), "1" | "true"))
+    })
+}
+
+struct StageScript {
+    path: PathBuf,
+    timeout: Option<Duration>,
+    parallel: bool,
+}
+
+/// Stage scripts of active modules in execution order
+fn stage_scripts(stage: &str) -> Result<Vec<StageScript>> {
+    let mut scripts = Vec::new();
     foreach_active_module(|module| {
-        let script_path = module.join(format!("{stage}.sh"));
-        if !script_path.exists() {
-            return Ok(());
+        let path = module.join(format!("{stage}.sh"));
+        if path.exists() {
+            scripts.push(StageScript {
+                path,
+                timeout: script_timeout(stage, Some(module)),
+                parallel: runs_in_parallel(module),
+            });
         }
-
-        exec_script(&script_path, block)
+        Ok(())
     })?;
+    Ok(scripts)
+}
+
+/// Run `scripts` in order with at most `concurrency` of them at once, reaping all of them
+///
+/// Scripts of a blocking stage are killed at their timeout. Scripts of an async stage are never
+/// killed, one running past its timeout just stops taking up a slot.
+fn run_scheduled(scripts: Vec<StageScript>, concurrency: usize, block: bool) {
+    thread::scope(|scope| {
+        let mut running: Vec<(Instant, Option<Duration>, thread::ScopedJoinHandle<()>)> =
+            Vec::new();
+        for script in scripts {
+            loop {
+                running.retain(|(started, timeout, handle)| {
+                    !handle.is_finished() && timeout.is_none_or(|t| started.elapsed() < t)
+                });
+                if running.len() < concurrency {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+
+            let kill_after = if block { script.timeout } else { None };
+            let handle = scope.spawn(move || {
+                if let Err(e) = exec_script(&script.path, true, kill_after) {
+                    warn!("{e}");
+                }
+            });
+            running.push((Instant::now(), script.timeout, handle));
+        }
+    });
+}
+
+pub fn exec_stage_script(stage: &str, block: bool) -> Result<()> {
+    if !block {
+        return spawn_stage_scheduler(stage);
+    }
+
+    // modules opting into parallel post-fs-data run together, in order with the others
+    let concurrency = script_concurrency();
+    let mut batch = Vec::new();
+    for script in stage_scripts(stage)? {
+        if script.parallel && stage == "post-fs-data" {
+            batch.push(script);
+            continue;
+        }
+        run_scheduled(std::mem::take(&mut batch), concurrency, true);
+        // one broken module must not keep the rest of the stage from running
+        if let Err(e) = exec_script(&script.path, true, script.timeout) {
+            warn!("{e}");
+        }
+    }
+    run_scheduled(batch, concurrency, true);
+    Ok(())
+}
+
+/// Hand the scripts of an async stage to a detached apd, so the boot event returns at once
+fn spawn_stage_scheduler(stage: &str) -> Result<()> {
+    let mut command = Command::new(defs::DAEMON_PATH);
+    command.process_group(0);
+    unsafe {
+        command.pre_exec(|| {
+            switch_cgroups();
+            Ok(())
+        });
+    }
+    command
+        .args(["stage-scripts", stage])
+        .spawn()
+        .with_context(|| format!("Failed to start {stage} script scheduler"))?;
+    Ok(())
+}
+
+/// Run the scripts of an async stage to completion, a bounded number at a time
+pub fn run_async_stage(stage: &str) -> Result<()> {
+    run_scheduled(stage_scripts(stage)?, script_concurrency(), false);
     Ok(())
 }
 
 pub fn exec_common_scripts(dir: &str, wait: bool) -> Result<()> {
+    let timeout = script_timeout(dir.trim_end_matches(".d"), None);
     let script_dir = Path::new(defs::ADB_DIR).join(dir);
     if !script_dir.exists() {
         info!("{} not exists, skip", script_dir.display());
@@ -290,7 +543,7 @@ pub fn exec_common_scripts(dir: &str, wait: bool) -> Result<()> {
             continue;
         }
 
-        exec_script(path, wait)?;
+        exec_script(path, wait, timeout)?;
     }
 
     Ok(())
@@ -325,9 +578,7 @@ pub fn prune_modules() -> Result<()> {
         let module_id = module.file_name().and_then(|n| n.to_str()).unwrap_or("");
 
         // Check if this is a metamodule
-        let is_metamodule = read_module_prop(module)
-            .map(|props| metamodule::is_metamodule(&props))
-            .unwrap_or(false);
+        let is_metamodule = Module::load(module).is_ok_and(|module| module.metamodule);
 
         if is_metamodule {
             info!("Removing metamodule symlink");
@@ -341,7 +592,7 @@ pub fn prune_modules() -> Result<()> {
         // Then execute module's own uninstall.sh
         let uninstaller = module.join("uninstall.sh");
         if uninstaller.exists()
-            && let Err(e) = exec_script(uninstaller, true)
+            && let Err(e) = exec_script(uninstaller, true, None)
         {
             warn!("Failed to exec uninstaller: {e}");
         }
@@ -350,11 +601,20 @@ pub fn prune_modules() -> Result<()> {
         if let Err(e) = module_config::clear_module_configs(module_id) {
             warn!("Failed to clear configs for {module_id}: {e}");
         }
+        module_status::clear_status(module_id);
 
         // Finally remove the module directory
-        if let Err(e) = remove_dir_all(module) {
+        let version = module_journal::version_code(module);
+        let result = remove_dir_all(module);
+        if let Err(e) = &result {
             warn!("Failed to remove {}: {e}", module.display());
         }
+        module_journal::record(
+            "prune",
+            module_id,
+            JournalEntry::versions(version, None),
+            &result,
+        );
 
         Ok(())
     })?;
@@ -372,7 +632,44 @@ pub fn prune_modules() -> Result<()> {
     Ok(())
 }
 
-fn _install_module(zip: &str) -> Result<()> {
+/// A module needs mounting if it has a system/ dir and no skip_mount file
+fn zip_needs_mount(zip_path: &Path) -> Result<bool> {
+    let zip_file = fs::File::open(zip_path)?;
+    let archive = zip::ZipArchive::new(zip_file)?;
+    let has_system = archive.file_names().any(|name| name.starts_with("system/"));
+    let has_skip_mount = archive.file_names().any(|name| name == "skip_mount");
+    Ok(has_system && !has_skip_mount)
+}
+
+/// Read a file from a module zip, `None` if the zip does not contain it
+fn read_zip_entry(zip_path: &Path, name: &str) -> Result<Option<String>> {
+    let zip_file = fs::File::open(zip_path)?;
+    let mut archive = zip::ZipArchive::new(zip_file)?;
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .with_context(|| format!("Failed to read {name} from zip"))?;
+    Ok(Some(content))
+}
+
+/// Get the id of the installed metamodule if it is not `module_id`
+fn conflicting_metamodule(module_id: &str) -> Option<String> {
+    if !metamodule::has_metamodule() {
+        return None;
+    }
+    let existing_path = metamodule::get_metamodule_path()?;
+    let existing_id = Module::load(&existing_path)
+        .map(|module| module.id)
+        .unwrap_or_else(|_| "unknown".to_string());
+    (existing_id != module_id).then_some(existing_id)
+}
+
+fn _install_module(zip_path: &Path) -> Result<()> {
     ensure_boot_completed()?;
 
     // print banner
@@ -385,35 +682,22 @@ fn _install_module(zip: &str) -> Result<()> {
     ensure_dir_exists(defs::BINARY_DIR).with_context(|| "Failed to create bin dir")?;
 
     // read the module_id from zip
-    let mut buffer: Vec<u8> = Vec::new();
-    let entry_path = PathBuf::from_str("module.prop")?;
-    let zip_path = PathBuf::from_str(zip)?;
-    let zip_path = zip_path.canonicalize()?;
-    zip_extract_file_to_memory(&zip_path, &entry_path, &mut buffer)?;
-    let mut module_prop = HashMap::new();
-    PropertiesIter::new_with_encoding(Cursor::new(buffer), encoding_rs::UTF_8).read_into(
-        |k, v| {
-            module_prop.insert(k, v);
-        },
-    )?;
-    info!("module prop: {:?}", module_prop);
+    let limits = ExtractLimits::load();
+    archive::check_archive(zip_path, &limits)?;
+    let signer = module_signature::check_signature(zip_path)?;
+    let module = Module::read_zip(zip_path)?;
+    info!("module prop: {:?}", module.props);
 
-    let Some(module_id) = module_prop.get("id") else {
-        bail!("module id not found in module.prop!");
-    };
-    let module_id = module_id.trim();
+    module.validate_id()?;
+    let module_id = module.id.as_str();
+    module_deps::check_install(&module)?;
+    module_compat::check_install(&module)?;
 
     // Check if this module is a metamodule
-    let is_metamodule = metamodule::is_metamodule(&module_prop);
+    let is_metamodule = module.metamodule;
 
     // Check if module needs mounting (has system/ dir and no skip_mount file)
-    let needs_mount = {
-        let zip_file = fs::File::open(&zip_path)?;
-        let archive = zip::ZipArchive::new(zip_file)?;
-        let has_system = archive.file_names().any(|name| name.starts_with("system/"));
-        let has_skip_mount = archive.file_names().any(|name| name == "skip_mount");
-        has_system && !has_skip_mount
-    };
+    let needs_mount = zip_needs_mount(zip_path)?;
 
     // Check if it's safe to install regular module
     if !is_metamodule
@@ -436,7 +720,6 @@ fn _install_module(zip: &str) -> Result<()> {
     }
 
     let modules_dir = Path::new(defs::MODULE_DIR);
-    let modules_update_dir = Path::new(defs::MODULE_UPDATE_DIR);
     if !Path::new(modules_dir).exists() {
         fs::create_dir(modules_dir).expect("Failed to create modules folder");
         let permissions = fs::Permissions::from_mode(0o700);
@@ -447,50 +730,82 @@ fn _install_module(zip: &str) -> Result<()> {
         info!("Installing metamodule: {module_id}");
 
         // Check if there's already a metamodule installed
-        if metamodule::has_metamodule()
-            && let Some(existing_path) = metamodule::get_metamodule_path()
-        {
-            let existing_id = read_module_prop(&existing_path)
-                .ok()
-                .and_then(|m| m.get("id").cloned())
-                .unwrap_or_else(|| "unknown".to_string());
-
-            if existing_id != module_id {
-                println!("\n❌ Installation Failed");
-                println!("┌────────────────────────────────");
-                println!("│ A metamodule is already installed");
-                println!("│   Current metamodule: {existing_id}");
-                println!("│");
-                println!("│ Only one metamodule can be active at a time.");
-                println!("│");
-                println!("│ To install this metamodule:");
-                println!("│   1. Uninstall the current metamodule");
-                println!("│   2. Reboot your device");
-                println!("│   3. Install the new metamodule");
-                println!("└─────────────────────────────────\n");
-                bail!("Cannot install multiple metamodules");
+        if let Some(existing_id) = conflicting_metamodule(module_id) {
+            println!("\n❌ Installation Failed");
+            println!("┌────────────────────────────────");
+            println!("│ A metamodule is already installed");
+            println!("│   Current metamodule: {existing_id}");
+            println!("│");
+            println!("│ Only one metamodule can be active at a time.");
+            println!("│");
+            println!("│ To install this metamodule:");
+            println!("│   1. Uninstall the current metamodule");
+            println!("│   2. Reboot your device");
+            println!("│   3. Install the new metamodule");
+            println!("└─────────────────────────────────\n");
+            bail!("Cannot install multiple metamodules");
+        }
+    }
+
+    let transaction = InstallTransaction::begin(module_id)?;
+    match stage_module(
+        zip_path,
+        module_id,
+        is_metamodule,
+        &limits,
+        signer.as_deref(),
+    ) {
+        Ok(()) => {
+            mark_update()?;
+            transaction.commit()
+        }
+        Err(e) => {
+            println!("- Installation failed, restoring previous state");
+            if let Err(rollback_err) = transaction.rollback() {
+                warn!("Failed to roll back install of {module_id}: {rollback_err}");
             }
+            Err(e)
         }
     }
+}
 
-    let module_dir = format!("{}{}", modules_dir.display(), module_id);
-    let _module_update_dir = format!("{}{}", modules_update_dir.display(), module_id);
+/// Extract and install module `module_id`, the caller rolls back on failure
+fn stage_module(
+    zip: &Path,
+    module_id: &str,
+    is_metamodule: bool,
+    limits: &ExtractLimits,
+    signer: Option<&str>,
+) -> Result<()> {
+    let module_dir = format!("{MODULE_DIR}{module_id}");
+    let _module_update_dir = format!("{MODULE_UPDATE_DIR}{module_id}");
     info!("module dir: {}", module_dir);
-    if !Path::new(&module_dir.clone()).exists() {
-        fs::create_dir(module_dir.clone()).expect("Failed to create module folder");
-        let permissions = fs::Permissions::from_mode(0o700);
-        fs::set_permissions(module_dir.clone(), permissions).expect("Failed to set permissions");
+    if !Path::new(&module_dir).exists() {
+        fs::create_dir(&module_dir).context("Failed to create module folder")?;
+        fs::set_permissions(&module_dir, fs::Permissions::from_mode(0o700))
+            .context("Failed to set permissions")?;
     }
     // unzip the image and move it to modules_update/<id> dir
-    let file = fs::File::open(zip)?;
-    let mut archive = zip::ZipArchive::new(file)?;
-    archive.extract(&_module_update_dir)?;
+    archive::extract_archive(Path::new(zip), Path::new(&_module_update_dir), limits, &[])?;
 
     println!("- Running module installer");
     exec_install_script(zip, is_metamodule)?;
 
-    // set permission and selinux context for $MOD/system
-    let module_system_dir = PathBuf::from(module_dir.clone()).join("system");
+    // verify the staged module before anything refers to it
+    let staged = Module::load(Path::new(&_module_update_dir))
+        .context("Installer did not stage the module")?;
+    ensure!(
+        staged.id == module_id,
+        "Staged module.prop does not match module id {module_id}"
+    );
+
+    // the zip may carry its own signer file, always overwrite it
+    module_signature::record_signer(Path::new(&_module_update_dir), signer)?;
+    module_signature::record_signer(Path::new(&module_dir), signer)?;
+
+    // set permission and selinux context for the staged system/, the installed module is only
+    // replaced at boot, so the transaction never has to undo this
+    let module_system_dir = PathBuf::from(&_module_update_dir).join("system");
     if module_system_dir.exists() {
         #[cfg(unix)]
         fs::set_permissions(&module_system_dir, fs::Permissions::from_mode(0o755))?;
@@ -502,129 +817,231 @@ fn _install_module(zip: &str) -> Result<()> {
         println!("- Creating metamodule symlink");
         metamodule::ensure_symlink(&module_dir)?;
     }
-
-    mark_update()?;
     Ok(())
 }
 
+/// Copy `zip` and its signature to a dir only root can write
+///
+/// The zip is checked, verified and installed from this copy, so it cannot be swapped between
+/// the checks and the installer reading it.
+fn copy_zip_private(zip: &Path) -> Result<PathBuf> {
+    let dir = Path::new(defs::MODULE_INSTALL_ZIP_DIR);
+    ensure_dir_exists(dir).context("Failed to create install zip dir")?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+
+    let private = dir.join(format!("{}.zip", std::process::id()));
+    let copy = |from: &Path, to: &Path| -> Result<()> {
+        let _ = fs::remove_file(to);
+        let mut out = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(to)?;
+        std::io::copy(&mut fs::File::open(from)?, &mut out)?;
+        Ok(())
+    };
+    copy(zip, &private).with_context(|| format!("Failed to copy {}", zip.display()))?;
+    let signature = module_signature::signature_path(zip);
+    if signature.exists() {
+        copy(&signature, &module_signature::signature_path(&private))
+            .context("Failed to copy module signature")?;
+    }
+    Ok(private)
+}
+
 pub fn install_module(zip: &str) -> Result<()> {
-    _install_module(zip)
+    let private = copy_zip_private(Path::new(zip))?;
+    let result = install_private_zip(&private);
+    let _ = fs::remove_file(module_signature::signature_path(&private));
+    let _ = fs::remove_file(&private);
+    result
 }
 
-pub fn _uninstall_module(id: &str, update_dir: &str) -> Result<()> {
-    let dir = Path::new(update_dir);
-    ensure!(dir.exists(), "No module installed");
+fn install_private_zip(zip_path: &Path) -> Result<()> {
+    let module = Module::read_zip(zip_path).ok();
+    let id = module.as_ref().map_or("", |module| module.id.as_str());
+    let installed = Module::find(Path::new(defs::MODULE_DIR), id)
+        .ok()
+        .map(|installed| installed.version_code);
 
-    // iterate the modules_update dir, find the module to be removed
-    let dir = fs::read_dir(dir)?;
-    for entry in dir.flatten() {
-        let path = entry.path();
-        let module_prop = path.join("module.prop");
-        if !module_prop.exists() {
-            continue;
+    let result = _install_module(zip_path);
+
+    let entry = JournalEntry {
+        zip_sha256: module_signature::zip_digest(zip_path).ok().map(hex::encode),
+        ..JournalEntry::versions(installed, module.as_ref().map(|module| module.version_code))
+    };
+    let op = if installed.is_some() {
+        "update"
+    } else {
+        "install"
+    };
+    module_journal::record(op, id, entry, &result);
+    result
+}
+
+/// Extract a module zip for the installer script, without its signature files
+pub fn extract_module(zip: &str, dest: &str) -> Result<()> {
+    archive::extract_archive(
+        Path::new(zip),
+        Path::new(dest),
+        &ExtractLimits::load(),
+        &["META-INF"],
+    )
+}
+
+/// Check the syntax of `rules`, then apply them to `policy` or the live policy if there is one
+fn check_sepolicy_rule(rules: &str, policy: Option<&str>) -> Result<&'static str> {
+    crate::sepolicy::check_syntax(rules)?;
+
+    let policy = match policy {
+        Some(policy) => Path::new(policy),
+        None if Path::new(crate::sepolicy::LIVE_POLICY_PATH).exists() => {
+            Path::new(crate::sepolicy::LIVE_POLICY_PATH)
         }
-        let content = fs::read(module_prop)?;
-        let mut module_id: String = String::new();
-        PropertiesIter::new_with_encoding(Cursor::new(content), encoding_rs::UTF_8).read_into(
-            |k, v| {
-                if k.eq("id") {
-                    module_id = v;
-                }
-            },
-        )?;
-        if module_id.eq(id) {
-            let remove_file = path.join(defs::REMOVE_FILE_NAME);
-            fs::File::create(remove_file).with_context(|| "Failed to create remove file.")?;
-            break;
+        None => return Ok("syntax ok, no policy to apply it to, pass --policy"),
+    };
+    let rule_path =
+        std::env::temp_dir().join(format!("apd-verify-{}-sepolicy.rule", std::process::id()));
+    fs::write(&rule_path, rules)?;
+    let result = crate::sepolicy::check_rule(&rule_path, Some(policy));
+    let _ = fs::remove_file(&rule_path);
+    result.map(|_| "ok")
+}
+
+/// Check a module zip the way install would, without changing anything
+pub fn verify_module(zip: &str, policy: Option<&str>) -> Result<()> {
+    let zip_path = Path::new(zip)
+        .canonicalize()
+        .with_context(|| format!("{zip} not found"))?;
+    let mut problems = Vec::new();
+
+    archive::check_archive(&zip_path, &ExtractLimits::load())?;
+    let module = Module::read_zip(&zip_path)?;
+    if let Err(e) = module.validate_id() {
+        problems.push(e.to_string());
+    }
+    println!("id: {}", module.id);
+    for key in ["name", "version", "author"] {
+        match module.prop(key) {
+            Some(value) => println!("{key}: {value}"),
+            None => problems.push(format!("{key} not found in module.prop")),
         }
     }
+    match module.prop("versionCode") {
+        Some(code) if code.trim().parse::<i64>().is_ok() => println!("versionCode: {code}"),
+        Some(code) => problems.push(format!("versionCode '{code}' is not a number")),
+        None => problems.push("versionCode not found in module.prop".to_string()),
+    }
+
+    match read_zip_entry(&zip_path, "sepolicy.rule")? {
+        Some(rules) => match check_sepolicy_rule(&rules, policy) {
+            Ok(status) => println!("sepolicy.rule: {status}"),
+            Err(e) => problems.push(format!("sepolicy.rule: {e:#}")),
+        },
+        None => println!("sepolicy.rule: none"),
+    }
 
-    // santity check
-    let target_module_path = format!("{update_dir}/{id}");
-    let target_module = Path::new(&target_module_path);
-    if target_module.exists() {
-        let remove_file = target_module.join(defs::REMOVE_FILE_NAME);
-        if !remove_file.exists() {
-            fs::File::create(remove_file).with_context(|| "Failed to create remove file.")?;
+    if let Err(e) = module_deps::parse_requires(&module) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = module_compat::validate(&module) {
+        problems.push(e.to_string());
+    }
+
+    match module_signature::signature_status(&zip_path) {
+        Ok(SignatureStatus::Trusted(signer)) => println!("signer: {signer}"),
+        Ok(SignatureStatus::Unsigned) => println!("signer: none"),
+        Ok(SignatureStatus::Untrusted) => println!("signer: untrusted"),
+        Err(e) => problems.push(format!("signature: {e}")),
+    }
+
+    let needs_mount = zip_needs_mount(&zip_path)?;
+    println!("metamodule: {}", module.metamodule);
+    println!("needs_mount: {needs_mount}");
+    if module.metamodule {
+        if let Some(existing_id) = conflicting_metamodule(&module.id) {
+            problems.push(format!("metamodule {existing_id} is already installed"));
         }
+    } else if needs_mount && metamodule::check_install_safety().is_err() {
+        problems.push("install is blocked by the active metamodule installer".to_string());
+    }
+
+    if let Some(script) = read_zip_entry(&zip_path, "customize.sh")? {
+        println!("customize.sh:");
+        println!("{script}");
+    }
+
+    if problems.is_empty() {
+        println!("- Module zip is valid");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("! {problem}");
     }
+    bail!("Module zip has {} problem(s)", problems.len());
+}
 
-    let _ = mark_module_state(id, defs::REMOVE_FILE_NAME, true);
+pub fn _uninstall_module(id: &str, update_dir: &str) -> Result<()> {
+    let dir = Path::new(update_dir);
+    ensure!(dir.exists(), "No module installed");
+
+    let module = Module::find(dir, id)?;
+    if !module.flags.remove {
+        fs::File::create(module.path.join(defs::REMOVE_FILE_NAME))
+            .with_context(|| "Failed to create remove file.")?;
+    }
+
+    let _ = mark_module_state(&module.dir_name(), defs::REMOVE_FILE_NAME, true);
     Ok(())
 }
 pub fn uninstall_module(id: &str) -> Result<()> {
-    _uninstall_module(id, defs::MODULE_DIR)?;
-    mark_update()?;
-    Ok(())
+    let dependents = module_deps::dependents(id, &module_deps::installed_modules(false));
+    if !dependents.is_empty() {
+        warn!("Uninstalling {id}, which is required by {dependents:?}");
+        println!("! Modules depending on {id}: {}", dependents.join(", "));
+    }
+    let version = Module::find(Path::new(defs::MODULE_DIR), id)
+        .ok()
+        .map(|module| module.version_code);
+    let result = _uninstall_module(id, defs::MODULE_DIR).and_then(|_| mark_update());
+    module_journal::record(
+        "uninstall",
+        id,
+        JournalEntry::versions(version, None),
+        &result,
+    );
+    result
 }
 
 pub fn _undo_uninstall_module(id: &str, update_dir: &str) -> Result<()> {
     let dir = Path::new(update_dir);
     ensure!(dir.exists(), "No module installed");
 
-    let mut found = false;
-    for entry in fs::read_dir(dir)?.flatten() {
-        let path = entry.path();
-        let module_prop = path.join("module.prop");
-        if !module_prop.exists() {
-            continue;
-        }
-
-        let content = fs::read(&module_prop)?;
-        let mut module_id = String::new();
-
-        PropertiesIter::new_with_encoding(Cursor::new(content), encoding_rs::UTF_8).read_into(
-            |k, v| {
-                if k == "id" {
-                    module_id = v;
-                }
-            },
-        )?;
-        if module_id == id {
-            let remove_file = path.join(defs::REMOVE_FILE_NAME);
-            fs::remove_file(remove_file).with_context(|| "Failed to remove removefile.")?;
-            found = true;
-            break;
-        }
-    }
-
-    ensure!(found, "Module not found");
+    let module = Module::find(dir, id).context("Module not found")?;
+    fs::remove_file(module.path.join(defs::REMOVE_FILE_NAME))
+        .with_context(|| "Failed to remove removefile.")?;
 
-    let _ = mark_module_state(id, defs::REMOVE_FILE_NAME, false);
+    let _ = mark_module_state(&module.dir_name(), defs::REMOVE_FILE_NAME, false);
     Ok(())
 }
 pub fn undo_uninstall_module(id: &str) -> Result<()> {
-    _undo_uninstall_module(id, defs::MODULE_DIR)?;
-    mark_update()?;
-    Ok(())
-}
-
-/// Read module.prop from the given module path and return as a HashMap
-pub fn read_module_prop(module_path: &Path) -> Result<HashMap<String, String>> {
-    let module_prop = module_path.join("module.prop");
-    ensure!(
-        module_prop.exists(),
-        "module.prop not found in {}",
-        module_path.display()
+    let version = Module::find(Path::new(defs::MODULE_DIR), id)
+        .ok()
+        .map(|module| module.version_code);
+    let result = _undo_uninstall_module(id, defs::MODULE_DIR).and_then(|_| mark_update());
+    module_journal::record(
+        "undo_uninstall",
+        id,
+        JournalEntry::versions(version, version),
+        &result,
     );
-
-    let content = std::fs::read(&module_prop)
-        .with_context(|| format!("Failed to read module.prop: {}", module_prop.display()))?;
-
-    let mut prop_map: HashMap<String, String> = HashMap::new();
-    PropertiesIter::new_with_encoding(Cursor::new(content), encoding_rs::UTF_8)
-        .read_into(|k, v| {
-            prop_map.insert(k, v);
-        })
-        .with_context(|| format!("Failed to parse module.prop: {}", module_prop.display()))?;
-
-    Ok(prop_map)
+    result
 }
 
 pub fn run_action(id: &str) -> Result<()> {
     let action_script_path = format!("/data/adb/modules/{}/action.sh", id);
     if Path::new(&action_script_path).exists() {
-        let _ = exec_script(&action_script_path, true);
+        let _ = exec_script(&action_script_path, true, None);
     } else {
         //if no action.sh, try to run lua action
         lua::run_lua(id, "action", false, true).map_err(|e| anyhow::anyhow!("{}", e))?;
@@ -639,6 +1056,8 @@ fn _change_module_state(module_dir: &str, mid: &str, enable: bool) -> Result<()>
 
     let disable_path = src_module.join(defs::DISABLE_FILE_NAME);
     if enable {
+        module_compat::clear_incompatibility(src_module);
+        module_quarantine::clear_quarantine(src_module);
         if disable_path.exists() {
             fs::remove_file(&disable_path).with_context(|| {
                 format!("Failed to remove disable file: {}", &disable_path.display())
@@ -664,8 +1083,15 @@ pub fn _enable_module(id: &str, update_dir: &Path) -> Result<()> {
 
 pub fn enable_module(id: &str) -> Result<()> {
     let update_dir = Path::new(defs::MODULE_DIR);
-    _enable_module(id, update_dir)?;
-    Ok(())
+    let version = module_journal::version_code(&update_dir.join(id));
+    let result = _enable_module(id, update_dir);
+    module_journal::record(
+        "enable",
+        id,
+        JournalEntry::versions(version, version),
+        &result,
+    );
+    result
 }
 
 pub fn _disable_module(id: &str, update_dir: &Path) -> Result<()> {
@@ -679,9 +1105,15 @@ pub fn _disable_module(id: &str, update_dir: &Path) -> Result<()> {
 
 pub fn disable_module(id: &str) -> Result<()> {
     let module_dir = Path::new(defs::MODULE_DIR);
-    _disable_module(id, module_dir)?;
-
-    Ok(())
+    let version = module_journal::version_code(&module_dir.join(id));
+    let result = _disable_module(id, module_dir);
+    module_journal::record(
+        "disable",
+        id,
+        JournalEntry::versions(version, version),
+        &result,
+    );
+    result
 }
 
 pub fn _disable_all_modules(dir: &str) -> Result<()> {
@@ -707,7 +1139,7 @@ pub fn disable_all_modules() -> Result<()> {
     Ok(())
 }
 
-fn _list_modules(path: &str) -> Vec<HashMap<String, String>> {
+fn _list_modules(path: &str) -> Vec<ListEntry> {
     // Load all module configs once to minimize I/O overhead
     let all_configs = match module_config::get_all_module_configs() {
         Ok(configs) => configs,
@@ -717,78 +1149,49 @@ fn _list_modules(path: &str) -> Vec<HashMap<String, String>> {
         }
     };
 
-    // first check enabled modules
-    let dir = fs::read_dir(path);
-    let Ok(dir) = dir else {
+    // list in execution order
+    let Ok(dir) = sorted_module_dirs(Path::new(path)) else {
         return Vec::new();
     };
 
-    let mut modules: Vec<HashMap<String, String>> = Vec::new();
+    let mut modules: Vec<ListEntry> = Vec::new();
+    let installed = module_deps::installed_modules(false);
+    let mut order = 0;
 
-    for entry in dir.flatten() {
-        let path = entry.path();
+    for path in dir {
         info!("path: {}", path.display());
-        let module_prop = path.join("module.prop");
-        if !module_prop.exists() {
+        if !path.join("module.prop").exists() {
             continue;
         }
-        let content = fs::read(&module_prop);
-        let Ok(content) = content else {
-            warn!("Failed to read file: {}", module_prop.display());
-            continue;
+        let module = match Module::load(&path) {
+            Ok(module) => module,
+            Err(e) => {
+                warn!("{e:#}");
+                continue;
+            }
         };
-        let mut module_prop_map: HashMap<String, String> = HashMap::new();
-        let encoding = encoding_rs::UTF_8;
-
-        if PropertiesIter::new_with_encoding(Cursor::new(content), encoding)
-            .read_into(|k, v| {
-                module_prop_map.insert(k, v);
-            })
-            .is_err()
-        {
-            warn!("Failed to parse module.prop: {}", module_prop.display());
-            continue;
-        }
+        let mut entry = module.to_list_entry();
 
-        if !module_prop_map.contains_key("id") || module_prop_map["id"].is_empty() {
-            match entry.file_name().to_str() {
-                Some(id) => {
-                    info!("Use dir name as module id: {}", id);
-                    module_prop_map.insert("id".to_owned(), id.to_owned());
-                }
-                _ => {
-                    info!("Failed to get module id: {:?}", module_prop);
-                    continue;
-                }
-            }
+        // position among the modules whose scripts actually run, empty if inactive
+        if module.flags.enabled && !module.flags.remove {
+            order += 1;
+            entry.order = order.to_string();
         }
-
-        // Add enabled, update, remove flags
-        let enabled = !path.join(defs::DISABLE_FILE_NAME).exists();
-        let update = path.join(defs::UPDATE_FILE_NAME).exists();
-        let remove = path.join(defs::REMOVE_FILE_NAME).exists();
-        let web = path.join(defs::MODULE_WEB_DIR).exists();
-        let id = module_prop_map.get("id").map(|s| s.as_str()).unwrap_or("");
-        let id_lua_file = format!("{}.lua", id);
-        let action = path.join(defs::MODULE_ACTION_SH).exists() || path.join(&id_lua_file).exists();
-
-        module_prop_map.insert("enabled".to_owned(), enabled.to_string());
-        module_prop_map.insert("update".to_owned(), update.to_string());
-        module_prop_map.insert("remove".to_owned(), remove.to_string());
-        module_prop_map.insert("web".to_owned(), web.to_string());
-        module_prop_map.insert("action".to_owned(), action.to_string());
+        entry.broken_dependencies =
+            module_deps::broken_dependencies(&module, &installed).join(", ");
+        entry.incompatible = module_compat::read_incompatibility(&path).unwrap_or_default();
+        entry.quarantine = module_quarantine::read_quarantine(&path).unwrap_or_default();
+        entry.signer = module_signature::read_signer(&path).unwrap_or_default();
 
         // Apply module config overrides and extract managed features
-        if let Some(module_id) = module_prop_map.get("id")
-            && let Some(config) = all_configs.get(module_id.as_str())
-        {
+        if let Some(config) = all_configs.get(&module.id) {
             // Apply override.description
             if let Some(desc) = config.get("override.description") {
-                module_prop_map.insert("description".to_owned(), desc.clone());
+                entry.description = desc.clone();
             }
         }
 
-        modules.push(module_prop_map);
+        modules.push(entry);
     }
 
     modules
@@ -0,0 +1,159 @@
+//! Module compatibility gating
+//!
+//! module.prop may limit a module to API levels (`minApi`, `maxApi`), architectures
+//! (`arch=arm64,arm`) and APatch versions (`minApatch`, `maxApatch`). Install rejects a module
+//! which does not fit the device, and a module which stops fitting after an OTA or an APatch
+//! update gets disabled at boot with the reason recorded next to its flags.
+
+use std::{fs, path::Path};
+
+use anyhow::{Result, bail};
+use log::{info, warn};
+
+use crate::{
+    defs,
+    module::{ModuleType, foreach_module},
+    module_prop::Module,
+    utils::{ensure_file_exists, getprop},
+};
+
+pub struct DeviceInfo {
+    pub api: Option<i64>,
+    pub abi: Option<String>,
+    pub apatch: i64,
+}
+
+impl DeviceInfo {
+    pub fn current() -> Self {
+        Self {
+            api: getprop("ro.build.version.sdk").and_then(|sdk| sdk.trim().parse().ok()),
+            abi: getprop("ro.product.cpu.abi").map(|abi| abi.trim().to_string()),
+            apatch: defs::VERSION_CODE.trim().parse().unwrap_or_default(),
+        }
+    }
+}
+
+/// Map an ABI to the architecture names used by module installers
+fn arch_of_abi(abi: &str) -> &str {
+    match abi {
+        "arm64-v8a" => "arm64",
+        "armeabi-v7a" | "armeabi" => "arm",
+        "x86_64" => "x64",
+        other => other,
+    }
+}
+
+fn parse_bound(module: &Module, key: &str) -> Result<Option<i64>> {
+    match module.prop(key).map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => match value.parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => bail!("{key} '{value}' is not a number"),
+        },
+    }
+}
+
+/// Check that the compatibility keys of module.prop are well formed
+pub fn validate(module: &Module) -> Result<()> {
+    for key in ["minApi", "maxApi", "minApatch", "maxApatch"] {
+        parse_bound(module, key)?;
+    }
+    Ok(())
+}
+
+/// Describe why a module does not fit `device`, `None` if it does
+pub fn incompatibility(module: &Module, device: &DeviceInfo) -> Option<String> {
+    let bound = |key| parse_bound(module, key).ok().flatten();
+
+    if let Some(api) = device.api {
+        if let Some(min) = bound("minApi")
+            && api < min
+        {
+            return Some(format!("requires API {min} or newer, device has {api}"));
+        }
+        if let Some(max) = bound("maxApi")
+            && api > max
+        {
+            return Some(format!("requires API {max} or older, device has {api}"));
+        }
+    }
+
+    if let Some(abi) = &device.abi
+        && let Some(archs) = module.prop("arch")
+    {
+        let device_arch = arch_of_abi(abi);
+        let supported = archs
+            .split(',')
+            .map(str::trim)
+            .filter(|arch| !arch.is_empty())
+            .any(|arch| arch == device_arch || arch == abi);
+        if !supported {
+            return Some(format!("supports {archs}, device is {device_arch}"));
+        }
+    }
+
+    if let Some(min) = bound("minApatch")
+        && device.apatch < min
+    {
+        return Some(format!(
+            "requires APatch {min} or newer, running {}",
+            device.apatch
+        ));
+    }
+    if let Some(max) = bound("maxApatch")
+        && device.apatch > max
+    {
+        return Some(format!(
+            "requires APatch {max} or older, running {}",
+            device.apatch
+        ));
+    }
+    None
+}
+
+/// Refuse to install a module which does not fit this device
+pub fn check_install(module: &Module) -> Result<()> {
+    validate(module)?;
+    if let Some(reason) = incompatibility(module, &DeviceInfo::current()) {
+        bail!("Module is not compatible with this device: {reason}");
+    }
+    Ok(())
+}
+
+/// Disable enabled modules which no longer fit the device and record why
+pub fn disable_incompatible_modules() -> Result<()> {
+    let device = DeviceInfo::current();
+    foreach_module(ModuleType::Active, |module| {
+        let Ok(loaded) = Module::load(module) else {
+            return Ok(());
+        };
+        let Some(reason) = incompatibility(&loaded, &device) else {
+            return Ok(());
+        };
+
+        warn!("disable incompatible module {}: {reason}", module.display());
+        if let Err(e) = fs::write(module.join(defs::INCOMPATIBLE_FILE_NAME), &reason) {
+            warn!(
+                "Failed to record incompatibility of {}: {e}",
+                module.display()
+            );
+        }
+        ensure_file_exists(module.join(defs::DISABLE_FILE_NAME))
+    })
+}
+
+/// The reason a module was disabled as incompatible, if it was
+pub fn read_incompatibility(module: &Path) -> Option<String> {
+    fs::read_to_string(module.join(defs::INCOMPATIBLE_FILE_NAME))
+        .ok()
+        .map(|reason| reason.trim().to_string())
+}
+
+/// Forget a recorded incompatibility, the module is about to be enabled again
+pub fn clear_incompatibility(module: &Path) {
+    let path = module.join(defs::INCOMPATIBLE_FILE_NAME);
+    if path.exists() {
+        info!("clear incompatibility record of {}", module.display());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -88,7 +88,7 @@ fn validate_config_count(config: &HashMap<String, String>) -> Result<()> {
 }
 
 /// Get the config directory path for a module
-fn get_config_dir(module_id: &str) -> PathBuf {
+pub fn get_config_dir(module_id: &str) -> PathBuf {
     Path::new(defs::MODULE_CONFIG_DIR).join(module_id)
 }
 
@@ -0,0 +1,194 @@
+//! Module dependency and conflict declarations
+//!
+//! module.prop may declare `requires=id[>=versionCode],...` and `conflicts=id,...`. A staged
+//! update in `modules_update` counts as installed, so a dependency can be installed right
+//! before the module needing it without rebooting in between.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    module::{ModuleType, foreach_module},
+    module_prop::Module,
+};
+
+pub struct Requirement {
+    pub id: String,
+    pub min_version_code: Option<i64>,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.min_version_code {
+            Some(code) => write!(f, "{}>={code}", self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+/// What is known about an installed module when checking dependencies
+pub struct InstalledModule {
+    pub version_code: i64,
+    pub enabled: bool,
+    pub requires: Vec<Requirement>,
+    pub conflicts: Vec<String>,
+}
+
+fn split_list(value: Option<&str>) -> impl Iterator<Item = &str> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+pub fn parse_requires(module: &Module) -> Result<Vec<Requirement>> {
+    split_list(module.prop("requires"))
+        .map(|item| match item.split_once(">=") {
+            Some((id, code)) => match code.trim().parse() {
+                Ok(code) => Ok(Requirement {
+                    id: id.trim().to_string(),
+                    min_version_code: Some(code),
+                }),
+                Err(_) => bail!("Invalid versionCode in requirement '{item}'"),
+            },
+            None => Ok(Requirement {
+                id: item.to_string(),
+                min_version_code: None,
+            }),
+        })
+        .collect()
+}
+
+pub fn parse_conflicts(module: &Module) -> Vec<String> {
+    split_list(module.prop("conflicts"))
+        .map(str::to_string)
+        .collect()
+}
+
+fn installed_module(module: &Module) -> InstalledModule {
+    InstalledModule {
+        version_code: module.version_code,
+        enabled: module.flags.enabled,
+        requires: parse_requires(module).unwrap_or_default(),
+        conflicts: parse_conflicts(module),
+    }
+}
+
+/// Collect installed modules by id, skipping modules marked for removal
+///
+/// With `include_staged`, pending updates replace the installed version.
+pub fn installed_modules(include_staged: bool) -> HashMap<String, InstalledModule> {
+    let mut modules: HashMap<String, InstalledModule> = HashMap::new();
+    let mut collect = |path: &Path| {
+        let Ok(module) = Module::load(path) else {
+            return Ok(());
+        };
+        if module.flags.remove {
+            return Ok(());
+        }
+        let mut installed = installed_module(&module);
+        // staged updates keep the enabled state of the installed version
+        if let Some(current) = modules.get(&module.id) {
+            installed.enabled = current.enabled;
+        }
+        modules.insert(module.id, installed);
+        Ok(())
+    };
+    let _ = foreach_module(ModuleType::All, &mut collect);
+    if include_staged {
+        let _ = foreach_module(ModuleType::Updated, &mut collect);
+    }
+    modules
+}
+
+/// Describe requirements of a module which the installed modules do not satisfy
+pub fn unmet_requirements(
+    requires: &[Requirement],
+    installed: &HashMap<String, InstalledModule>,
+) -> Vec<String> {
+    requires
+        .iter()
+        .filter_map(|requirement| match installed.get(&requirement.id) {
+            None => Some(format!("{requirement} (missing)")),
+            Some(module) if !module.enabled => Some(format!("{requirement} (disabled)")),
+            Some(module)
+                if requirement
+                    .min_version_code
+                    .is_some_and(|code| module.version_code < code) =>
+            {
+                Some(format!("{requirement} (have {})", module.version_code))
+            }
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// Installed modules conflicting with module `id`, in either direction
+pub fn conflicting_modules(
+    id: &str,
+    conflicts: &[String],
+    installed: &HashMap<String, InstalledModule>,
+) -> Vec<String> {
+    let mut found: Vec<String> = installed
+        .iter()
+        .filter(|(other_id, other)| {
+            other_id.as_str() != id
+                && (conflicts.contains(other_id) || other.conflicts.iter().any(|c| c == id))
+        })
+        .map(|(other_id, _)| other_id.clone())
+        .collect();
+    found.sort();
+    found
+}
+
+/// Installed modules which require module `id`
+pub fn dependents(id: &str, installed: &HashMap<String, InstalledModule>) -> Vec<String> {
+    let mut found: Vec<String> = installed
+        .iter()
+        .filter(|(other_id, other)| {
+            other_id.as_str() != id && other.requires.iter().any(|r| r.id == id)
+        })
+        .map(|(other_id, _)| other_id.clone())
+        .collect();
+    found.sort();
+    found
+}
+
+/// Refuse to install a module conflicting with installed ones, warn about unmet requirements
+pub fn check_install(module: &Module) -> Result<()> {
+    let id = module.id.as_str();
+    let requires = parse_requires(module)?;
+    let conflicts = parse_conflicts(module);
+    let installed = installed_modules(true);
+
+    let conflicting = conflicting_modules(id, &conflicts, &installed);
+    if !conflicting.is_empty() {
+        bail!(
+            "Module {id} conflicts with installed modules: {}",
+            conflicting.join(", ")
+        );
+    }
+    for unmet in unmet_requirements(&requires, &installed) {
+        println!("! Unmet dependency: {unmet}");
+    }
+    Ok(())
+}
+
+/// Dependency problems of module `id` among the installed modules
+pub fn broken_dependencies(
+    module: &Module,
+    installed: &HashMap<String, InstalledModule>,
+) -> Vec<String> {
+    let mut problems = match parse_requires(module) {
+        Ok(requires) => unmet_requirements(&requires, installed),
+        Err(e) => vec![e.to_string()],
+    };
+    problems.extend(
+        conflicting_modules(&module.id, &parse_conflicts(module), installed)
+            .into_iter()
+            .map(|other| format!("conflicts with {other}")),
+    );
+    problems
+}
@@ -0,0 +1,224 @@
+//! Overlay file analysis for `apd module files` and `apd module conflicts`
+//!
+//! A module overlays `system/` plus the partitions the installer moved out of it (`vendor/`,
+//! `product/`, ...). Directories are merged, so only files, symlinks, replaced directories and
+//! whiteouts claim a path. When several modules claim one, the module first in execution order
+//! is expected to win, as it ends up as the topmost layer. A metamodule with its own mount logic
+//! may decide otherwise.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    ops::Bound::{Excluded, Unbounded},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{
+    defs, metamodule,
+    module::{ModuleType, foreach_module},
+    module_prop::Module,
+};
+
+/// Partitions the installer may move out of `system/` into their own module dir
+const PARTITIONS: [&str; 4] = ["vendor", "system_ext", "product", "odm"];
+const REPLACE_MARKER: &str = ".replace";
+const OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverlayKind {
+    File,
+    Symlink,
+    /// A directory hiding the original one instead of merging with it
+    Replace,
+    /// A whiteout removing the original path
+    Remove,
+}
+
+impl fmt::Display for OverlayKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::File => "file",
+            Self::Symlink => "symlink",
+            Self::Replace => "replace",
+            Self::Remove => "remove",
+        })
+    }
+}
+
+pub struct OverlayEntry {
+    /// Path on the device, e.g. `/system/bin/foo`
+    pub path: String,
+    pub kind: OverlayKind,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_opaque(dir: &Path) -> bool {
+    extattr::lgetxattr(dir, OPAQUE_XATTR).is_ok_and(|value| value == b"y")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn is_opaque(_dir: &Path) -> bool {
+    false
+}
+
+fn is_replaced(dir: &Path) -> bool {
+    dir.join(REPLACE_MARKER).exists() || is_opaque(dir)
+}
+
+fn walk(dir: &Path, target: &str, entries: &mut Vec<OverlayEntry>) {
+    if is_replaced(dir) {
+        entries.push(OverlayEntry {
+            path: target.to_string(),
+            kind: OverlayKind::Replace,
+        });
+    }
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut children: Vec<_> = read_dir.flatten().collect();
+    children.sort_by_key(|entry| entry.file_name());
+    for entry in children {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        let child_target = format!("{target}/{name}");
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let file_type = meta.file_type();
+
+        let kind = if file_type.is_dir() {
+            walk(&path, &child_target, entries);
+            continue;
+        } else if file_type.is_symlink() {
+            // the link the installer leaves behind for a partition moved out of system/
+            if target == "/system"
+                && PARTITIONS.contains(&name.as_str())
+                && fs::read_link(&path).is_ok_and(|link| link == Path::new("..").join(&name))
+            {
+                continue;
+            }
+            OverlayKind::Symlink
+        } else if file_type.is_char_device() && meta.rdev() == 0 {
+            OverlayKind::Remove
+        } else if name == REPLACE_MARKER {
+            continue;
+        } else {
+            OverlayKind::File
+        };
+        entries.push(OverlayEntry {
+            path: child_target,
+            kind,
+        });
+    }
+}
+
+/// Paths the module in `module` overlays, sorted by path
+pub fn overlay_entries(module: &Path) -> Vec<OverlayEntry> {
+    let mut entries = Vec::new();
+    walk(&module.join("system"), "/system", &mut entries);
+    for partition in PARTITIONS {
+        let dir = module.join(partition);
+        if dir.is_dir() {
+            walk(&dir, &format!("/{partition}"), &mut entries);
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+fn skips_mount(module: &Path) -> bool {
+    module.join("skip_mount").exists()
+}
+
+pub fn print_files(id: &str) -> Result<()> {
+    let module = Module::find(Path::new(defs::MODULE_DIR), id)?;
+    if skips_mount(&module.path) {
+        println!("! {id} has skip_mount, none of these paths are mounted");
+    }
+    for entry in overlay_entries(&module.path) {
+        println!("{:<8} {}", entry.kind, entry.path);
+    }
+    Ok(())
+}
+
+/// Whether `path` equals `claimed` or lies below it
+fn covers(claimed: &str, path: &str) -> bool {
+    path.strip_prefix(claimed)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+pub fn print_conflicts() -> Result<()> {
+    // foreach_module walks modules in execution order
+    let mut order = Vec::new();
+    let mut claims: BTreeMap<String, Vec<(usize, OverlayKind)>> = BTreeMap::new();
+    foreach_module(ModuleType::Active, |module| {
+        if skips_mount(module) {
+            return Ok(());
+        }
+        for entry in overlay_entries(module) {
+            claims
+                .entry(entry.path)
+                .or_default()
+                .push((order.len(), entry.kind));
+        }
+        order.push(
+            module
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        );
+        Ok(())
+    })?;
+
+    let mut conflicts = Vec::new();
+    for (path, claimers) in &claims {
+        let mut involved: Vec<(usize, String)> = claimers
+            .iter()
+            .map(|(index, kind)| (*index, kind.to_string()))
+            .collect();
+        // a replaced or removed path also hides whatever other modules put below it
+        if claimers
+            .iter()
+            .any(|(_, kind)| matches!(kind, OverlayKind::Replace | OverlayKind::Remove))
+        {
+            for (below, others) in claims.range::<String, _>((Excluded(path), Unbounded)) {
+                if !covers(path, below) {
+                    break;
+                }
+                for (index, _) in others {
+                    if !involved.iter().any(|(claimer, _)| claimer == index) {
+                        involved.push((*index, format!("below, {below}")));
+                    }
+                }
+            }
+        }
+        if involved.len() > 1 {
+            involved.sort_by_key(|(index, _)| *index);
+            conflicts.push((path, involved));
+        }
+    }
+
+    if conflicts.is_empty() {
+        println!("No overlay conflicts between active modules");
+        return Ok(());
+    }
+    if let Some(meta) = metamodule::get_metamodule_path() {
+        println!(
+            "! Winners assume execution order, metamodule {} may mount differently",
+            meta.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    for (path, involved) in conflicts {
+        let claimed_by: Vec<String> = involved
+            .iter()
+            .map(|(index, kind)| format!("{} ({kind})", order[*index]))
+            .collect();
+        println!("{path}");
+        println!("  claimed by: {}", claimed_by.join(", "));
+        println!("  expected winner: {}", order[involved[0].0]);
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Detailed view of a single module for `apd module info`
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    defs, metamodule, module_compat, module_config,
+    module_prop::Module,
+    module_quarantine, module_signature,
+    module_status::{ModuleStatus, ScriptResult},
+    utils::dir_size,
+};
+
+#[derive(Serialize, Debug)]
+pub struct ModuleFiles {
+    pub post_fs_data: bool,
+    pub service: bool,
+    pub sepolicy_rule: bool,
+    pub system_prop: bool,
+    pub lua: bool,
+    pub webroot: bool,
+    pub action: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PendingUpdate {
+    pub version: String,
+    pub version_code: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModuleInfo {
+    pub id: String,
+    pub props: BTreeMap<String, String>,
+    pub enabled: bool,
+    pub update: bool,
+    pub remove: bool,
+    pub priority: i64,
+    pub incompatible: Option<String>,
+    pub quarantine: Option<String>,
+    pub signer: Option<String>,
+    /// The staged version waiting for the next boot, if any
+    pub pending_update: Option<PendingUpdate>,
+    /// Bytes used by the module dir and its staged update
+    pub disk_usage: u64,
+    pub files: ModuleFiles,
+    pub metamodule: bool,
+    /// This module is the metamodule in charge of mounting
+    pub active_metamodule: bool,
+    pub config_keys: Vec<String>,
+    /// Latest result by stage
+    pub scripts: BTreeMap<String, ScriptResult>,
+}
+
+fn pending_update(update_dir: &Path) -> Option<PendingUpdate> {
+    let staged = Module::load(update_dir).ok()?;
+    Some(PendingUpdate {
+        version: staged.version,
+        version_code: staged.version_code,
+    })
+}
+
+pub fn module_info(id: &str) -> Result<ModuleInfo> {
+    let module = Module::find(Path::new(defs::MODULE_DIR), id)?;
+    let path = module.path.clone();
+    let update_dir = Path::new(defs::MODULE_UPDATE_DIR).join(module.dir_name());
+
+    let mut config_keys: Vec<String> = module_config::merge_configs(id)?.into_keys().collect();
+    config_keys.sort();
+
+    Ok(ModuleInfo {
+        id: id.to_string(),
+        enabled: module.flags.enabled,
+        update: module.flags.update,
+        remove: module.flags.remove,
+        priority: module.priority,
+        incompatible: module_compat::read_incompatibility(&path),
+        quarantine: module_quarantine::read_quarantine(&path),
+        signer: module_signature::read_signer(&path),
+        pending_update: pending_update(&update_dir),
+        disk_usage: dir_size(&path) + dir_size(&update_dir),
+        files: ModuleFiles {
+            post_fs_data: path.join("post-fs-data.sh").exists(),
+            service: path.join("service.sh").exists(),
+            sepolicy_rule: path.join("sepolicy.rule").exists(),
+            system_prop: path.join("system.prop").exists(),
+            lua: path.join(format!("{id}.lua")).exists(),
+            webroot: module.flags.web,
+            action: path.join(defs::MODULE_ACTION_SH).exists(),
+        },
+        metamodule: module.metamodule,
+        active_metamodule: metamodule::get_metamodule_path()
+            .is_some_and(|active| active.file_name() == path.file_name()),
+        config_keys,
+        scripts: ModuleStatus::load(id).unwrap_or_default().scripts,
+        props: module.props.into_iter().collect(),
+    })
+}
+
+pub fn print_info(id: &str) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&module_info(id)?)?);
+    Ok(())
+}
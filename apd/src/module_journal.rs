@@ -0,0 +1,179 @@
+//! Module operation journal
+//!
+//! Every module operation appends one JSON line to `module_journal.jsonl` under the working
+//! dir, recording the versions involved, the source zip digest and the outcome. The journal is
+//! never rewritten, so it also keeps the history of modules which are long gone.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{defs, module_prop::Module, package};
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct JournalEntry {
+    /// Seconds since the epoch
+    pub time: u64,
+    /// e.g. `install`, `update`, `enable`, `uninstall`, `prune`
+    pub op: String,
+    pub id: String,
+    /// Who asked for it: `cli`, `manager`, `lua` or `boot`
+    pub source: String,
+    pub from_version_code: Option<i64>,
+    pub to_version_code: Option<i64>,
+    /// Hex encoded SHA-256 of the installed zip
+    pub zip_sha256: Option<String>,
+    /// `ok` or the error message
+    pub result: String,
+}
+
+impl JournalEntry {
+    pub fn versions(from_version_code: Option<i64>, to_version_code: Option<i64>) -> Self {
+        Self {
+            from_version_code,
+            to_version_code,
+            ..Self::default()
+        }
+    }
+}
+
+static SOURCE: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// Attribute the following operations of this process to `source`
+pub fn set_source(source: &'static str) {
+    if let Ok(mut current) = SOURCE.lock() {
+        *current = Some(source);
+    }
+}
+
+/// Whether an ancestor of this process is the manager app
+fn started_by_manager() -> bool {
+    let mut manager = None;
+    let mut pid = std::os::unix::process::parent_id();
+    while pid > 1 {
+        let Ok(status) = fs::read_to_string(format!("/proc/{pid}/status")) else {
+            return false;
+        };
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.split_whitespace().next())
+                .and_then(|value| value.parse::<u32>().ok())
+        };
+        // only app processes can be the manager, skip reading its package otherwise
+        if field("Uid:").is_some_and(|uid| uid % 100000 >= 10000) {
+            let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+            let name = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+            let manager = manager.get_or_insert_with(package::manager_package_id);
+            if name == manager.as_bytes() {
+                return true;
+            }
+        }
+        pid = field("PPid:").unwrap_or_default();
+    }
+    false
+}
+
+fn source() -> String {
+    if let Ok(current) = SOURCE.lock()
+        && let Some(source) = *current
+    {
+        return source.to_string();
+    }
+    if started_by_manager() {
+        "manager".to_string()
+    } else {
+        "cli".to_string()
+    }
+}
+
+/// versionCode of the module in `module_dir`, if it is installed there
+pub fn version_code(module_dir: &Path) -> Option<i64> {
+    Module::load(module_dir)
+        .ok()
+        .map(|module| module.version_code)
+}
+
+/// Append an entry for `op` on module `id` with the outcome of `result`
+pub fn record<T, E: fmt::Display>(
+    op: &str,
+    id: &str,
+    entry: JournalEntry,
+    result: &std::result::Result<T, E>,
+) {
+    let entry = JournalEntry {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        op: op.to_string(),
+        id: id.to_string(),
+        source: source(),
+        result: match result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!("{e:#}"),
+        },
+        ..entry
+    };
+    let append = || -> Result<()> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(defs::MODULE_JOURNAL_PATH)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    };
+    if let Err(e) = append() {
+        warn!("Failed to journal {op} of {id}: {e}");
+    }
+}
+
+/// Operations which change what a module runs at the next boot
+const CHANGE_OPS: [&str; 5] = [
+    "install",
+    "update",
+    "apply_update",
+    "enable",
+    "undo_uninstall",
+];
+
+/// Time of the latest successful change of each module, seconds since the epoch
+pub fn last_changes() -> HashMap<String, u64> {
+    let content = fs::read_to_string(defs::MODULE_JOURNAL_PATH).unwrap_or_default();
+    let mut changes = HashMap::new();
+    for entry in content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<JournalEntry>(line).ok())
+        .filter(|entry| entry.result == "ok" && CHANGE_OPS.contains(&entry.op.as_str()))
+    {
+        let time = changes.entry(entry.id).or_default();
+        *time = entry.time.max(*time);
+    }
+    changes
+}
+
+/// Print the journal as JSON, only the entries of module `id` if given
+pub fn print_history(id: Option<&str>) -> Result<()> {
+    let content = fs::read_to_string(defs::MODULE_JOURNAL_PATH).unwrap_or_default();
+    let entries: Vec<JournalEntry> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|entry: &JournalEntry| id.is_none_or(|id| entry.id == id))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&entries)?);
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! Typed model of an installed module
+//!
+//! `parse_module_prop` is the only module.prop parser, used for installed modules, staged
+//! updates and zips alike. `Module` adds the well known keys as typed fields plus the state
+//! flags kept next to module.prop.
+//!
+//! Ids are checked against the strict id rules when a zip is installed. Installed modules only
+//! need an id usable as a dir name, as older versions installed modules under looser rules.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use java_properties::PropertiesIter;
+use serde::Serialize;
+use zip_extensions::zip_extract_file_to_memory;
+
+use crate::defs;
+
+/// Parse the content of a module.prop
+fn parse_module_prop(content: &[u8]) -> Result<HashMap<String, String>> {
+    let mut props = HashMap::new();
+    PropertiesIter::new_with_encoding(Cursor::new(content), encoding_rs::UTF_8).read_into(
+        |k, v| {
+            props.insert(k, v);
+        },
+    )?;
+    Ok(props)
+}
+
+/// Module ids name directories and config files, so they are kept to a safe charset
+pub fn validate_module_id(id: &str) -> Result<()> {
+    let re = regex_lite::Regex::new(r"^[a-zA-Z][a-zA-Z0-9._-]+$")?;
+    ensure!(re.is_match(id), "Invalid module id: '{id}'");
+    Ok(())
+}
+
+/// Ids of installed modules name their dirs, so they must not be able to leave the modules dir
+fn check_dir_name(id: &str) -> Result<()> {
+    ensure!(
+        !id.is_empty() && id != "." && id != ".." && !id.contains(['/', '\0']),
+        "Invalid module id: '{id}'"
+    );
+    Ok(())
+}
+
+fn is_true(value: &str) -> bool {
+    let value = value.trim();
+    value == "1" || value.eq_ignore_ascii_case("true")
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ModuleFlags {
+    pub enabled: bool,
+    pub update: bool,
+    pub remove: bool,
+    /// Has a webroot for the manager
+    pub web: bool,
+    /// Has an action.sh or a Lua action
+    pub action: bool,
+}
+
+impl ModuleFlags {
+    fn read(path: &Path, id: &str) -> Self {
+        Self {
+            enabled: !path.join(defs::DISABLE_FILE_NAME).exists(),
+            update: path.join(defs::UPDATE_FILE_NAME).exists(),
+            remove: path.join(defs::REMOVE_FILE_NAME).exists(),
+            web: path.join(defs::MODULE_WEB_DIR).exists(),
+            action: path.join(defs::MODULE_ACTION_SH).exists()
+                || path.join(format!("{id}.lua")).exists(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub version_code: i64,
+    pub author: String,
+    pub description: String,
+    pub priority: i64,
+    pub metamodule: bool,
+    pub flags: ModuleFlags,
+    /// The module dir, or the zip for a module which is not installed yet
+    pub path: PathBuf,
+    /// Every key of module.prop, including the ones above
+    pub props: HashMap<String, String>,
+}
+
+/// The module.prop keys `ListEntry` has fields for
+const LIST_KEYS: [&str; 7] = [
+    "id",
+    "name",
+    "version",
+    "versionCode",
+    "author",
+    "description",
+    "priority",
+];
+
+/// An entry of `apd module list`, every value is a string as the manager expects
+#[derive(Serialize, Debug, Default)]
+pub struct ListEntry {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    /// Always a number
+    #[serde(rename = "versionCode")]
+    pub version_code: String,
+    pub author: String,
+    pub description: String,
+    pub priority: String,
+    pub enabled: String,
+    pub update: String,
+    pub remove: String,
+    pub web: String,
+    pub action: String,
+    /// Position among the modules whose scripts run, empty if inactive
+    pub order: String,
+    pub broken_dependencies: String,
+    pub incompatible: String,
+    pub quarantine: String,
+    pub signer: String,
+    /// The other keys of module.prop
+    #[serde(flatten)]
+    pub props: BTreeMap<String, String>,
+}
+
+impl Module {
+    fn from_props(path: &Path, id: String, props: HashMap<String, String>) -> Self {
+        let text = |key: &str| props.get(key).cloned().unwrap_or_default();
+        let number = |key: &str| {
+            props
+                .get(key)
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or_default()
+        };
+        Self {
+            name: text("name"),
+            version: text("version"),
+            version_code: number("versionCode"),
+            author: text("author"),
+            description: text("description"),
+            priority: number("priority"),
+            metamodule: props.get("metamodule").is_some_and(|value| is_true(value)),
+            flags: ModuleFlags::default(),
+            path: path.to_path_buf(),
+            id,
+            props,
+        }
+    }
+
+    /// Load the module in `path`
+    ///
+    /// Modules installed by older versions may lack an id, the dir name stands in for it.
+    pub fn load(path: &Path) -> Result<Self> {
+        let prop_path = path.join("module.prop");
+        let content = fs::read(&prop_path)
+            .with_context(|| format!("Failed to read module.prop: {}", prop_path.display()))?;
+        let props = parse_module_prop(&content)
+            .with_context(|| format!("Failed to parse module.prop: {}", prop_path.display()))?;
+
+        let id = match props.get("id").map(|id| id.trim()) {
+            Some(id) if !id.is_empty() => id.to_string(),
+            _ => path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .with_context(|| format!("Failed to get module id of {}", path.display()))?
+                .to_string(),
+        };
+        check_dir_name(&id)?;
+
+        let mut module = Self::from_props(path, id, props);
+        module.flags = ModuleFlags::read(path, &module.id);
+        Ok(module)
+    }
+
+    /// Read the module.prop of a module zip, install checks the id with `validate_id`
+    pub fn read_zip(zip: &Path) -> Result<Self> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let entry_path = PathBuf::from_str("module.prop")?;
+        zip_extract_file_to_memory(&zip.to_path_buf(), &entry_path, &mut buffer)
+            .with_context(|| "module.prop not found in zip")?;
+        let props = parse_module_prop(&buffer)?;
+        let id = props
+            .get("id")
+            .map(|id| id.trim().to_string())
+            .unwrap_or_default();
+        Ok(Self::from_props(zip, id, props))
+    }
+
+    /// Check the id against the rules for new modules
+    pub fn validate_id(&self) -> Result<()> {
+        if !self.props.contains_key("id") {
+            bail!("module id not found in module.prop!");
+        }
+        validate_module_id(&self.id)
+    }
+
+    /// Find module `id` in `dir`
+    ///
+    /// Modules live in a dir named after their id, except some installed by older versions, so
+    /// the other dirs are searched for the declared id too.
+    pub fn find(dir: &Path, id: &str) -> Result<Self> {
+        check_dir_name(id)?;
+        let named = Self::load(&dir.join(id));
+        if named.as_ref().is_ok_and(|module| module.id == id) {
+            return named;
+        }
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                if let Ok(module) = Self::load(&entry.path())
+                    && module.id == id
+                {
+                    return Ok(module);
+                }
+            }
+        }
+        named.with_context(|| format!("module: {id} not found!"))
+    }
+
+    /// A module.prop key, untrimmed
+    pub fn prop(&self, key: &str) -> Option<&str> {
+        self.props.get(key).map(String::as_str)
+    }
+
+    /// The name of the module dir, which differs from the id for some older modules
+    pub fn dir_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The entry printed by `apd module list`, the state kept outside module.prop is left empty
+    pub fn to_list_entry(&self) -> ListEntry {
+        ListEntry {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            version_code: self.version_code.to_string(),
+            author: self.author.clone(),
+            description: self.description.clone(),
+            priority: self.priority.to_string(),
+            enabled: self.flags.enabled.to_string(),
+            update: self.flags.update.to_string(),
+            remove: self.flags.remove.to_string(),
+            web: self.flags.web.to_string(),
+            action: self.flags.action.to_string(),
+            props: self
+                .props
+                .iter()
+                .filter(|(key, _)| !LIST_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            ..ListEntry::default()
+        }
+    }
+}
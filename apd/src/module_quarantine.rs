@@ -0,0 +1,245 @@
+//! Boot loop detection and module quarantine
+//!
+//! post-fs-data counts boot attempts and boot-completed resets the count, so the count tells
+//! how many boots in a row never completed. Once `BOOT_LOOP_THRESHOLD` boots failed, the
+//! modules the journal saw installed, updated or enabled since the last completed boot get
+//! disabled. If none changed, the active modules are bisected: half of the suspects are
+//! disabled, and when the device still loops that half is innocent, so it is enabled again and
+//! half of the other half is disabled. Once a boot completes the culprit is among the disabled
+//! modules, which stay disabled. Quarantined modules keep the reason next to their flags until
+//! they are enabled again.
+//!
+//! The boot which quarantines already runs without the suspects, so it counts as the first
+//! attempt of the next round. A bisection round ends after `BISECT_ROUND_THRESHOLD` failed boots,
+//! finding the culprit among n modules costs about log2(n) more failed boots.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    defs,
+    module::{ModuleType, foreach_module},
+    module_journal,
+    module_prop::Module,
+    utils::ensure_file_exists,
+};
+
+/// Failed boots in a row before modules are quarantined
+const BOOT_LOOP_THRESHOLD: u32 = 3;
+/// Failed boots before the next bisection round, the loop was already established
+const BISECT_ROUND_THRESHOLD: u32 = 1;
+
+fn read_attempts() -> u32 {
+    fs::read_to_string(defs::BOOT_ATTEMPTS_PATH)
+        .ok()
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+/// When the last boot completed, seconds since the epoch
+fn last_good_boot() -> Option<u64> {
+    let modified = fs::metadata(defs::LAST_GOOD_BOOT_PATH)
+        .and_then(|meta| meta.modified())
+        .ok()?;
+    modified
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Progress of a bisection across boots, module dirs by name
+#[derive(Deserialize, Serialize)]
+struct Bisection {
+    /// The culprit is one of these
+    suspects: Vec<String>,
+    /// The suspects disabled for the current boot
+    disabled: Vec<String>,
+}
+
+impl Bisection {
+    fn load() -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(defs::QUARANTINE_BISECT_PATH).ok()?).ok()
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::write(defs::QUARANTINE_BISECT_PATH, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn clear() {
+        let _ = fs::remove_file(defs::QUARANTINE_BISECT_PATH);
+    }
+}
+
+/// Count this boot attempt, quarantining modules if the previous boots kept failing
+pub fn on_boot_attempt() -> Result<()> {
+    let failed = read_attempts();
+    let threshold = if Bisection::load().is_some() {
+        BISECT_ROUND_THRESHOLD
+    } else {
+        BOOT_LOOP_THRESHOLD
+    };
+    if failed >= threshold {
+        warn!("{failed} boots did not complete, quarantining modules");
+        quarantine_suspects(failed)?;
+        // this boot is the first attempt without the quarantined modules
+        fs::write(defs::BOOT_ATTEMPTS_PATH, "1")?;
+    } else {
+        fs::write(defs::BOOT_ATTEMPTS_PATH, (failed + 1).to_string())?;
+    }
+    Ok(())
+}
+
+/// The boot completed, forget the attempts and remember when modules last booted fine
+pub fn on_boot_completed() -> Result<()> {
+    fs::write(defs::BOOT_ATTEMPTS_PATH, "0")?;
+    fs::write(defs::LAST_GOOD_BOOT_PATH, "")?;
+    // the culprit is among the modules still disabled, they stay quarantined
+    Bisection::clear();
+    Ok(())
+}
+
+fn quarantine_suspects(failed: u32) -> Result<()> {
+    let modules_dir = Path::new(defs::MODULE_DIR);
+    let mut suspects = match Bisection::load() {
+        Some(bisection) => {
+            // the device kept looping without them, so they are innocent
+            for name in &bisection.disabled {
+                release(&modules_dir.join(name));
+            }
+            bisection
+                .suspects
+                .into_iter()
+                .filter(|name| !bisection.disabled.contains(name))
+                .collect()
+        }
+        None => {
+            let mut active: Vec<PathBuf> = Vec::new();
+            foreach_module(ModuleType::Active, |module| {
+                active.push(module.to_path_buf());
+                Ok(())
+            })?;
+            let changed = changed_modules(&active);
+            if !changed.is_empty() {
+                let reason = format!("changed before {failed} failed boots");
+                for module in changed {
+                    quarantine(module, &reason);
+                }
+                return Ok(());
+            }
+            active
+                .iter()
+                .filter_map(|module| module.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+    suspects.retain(|name| modules_dir.join(name).is_dir());
+
+    match suspects.len() {
+        0 => {
+            warn!("no active modules left to quarantine");
+            Bisection::clear();
+        }
+        1 => {
+            let reason = format!("last suspect left after {failed} failed boots");
+            quarantine(&modules_dir.join(&suspects[0]), &reason);
+            Bisection::clear();
+        }
+        count => {
+            let disabled = suspects[..count.div_ceil(2)].to_vec();
+            let reason = format!(
+                "bisecting after {failed} failed boots, {} of {count} suspects disabled",
+                disabled.len()
+            );
+            for name in &disabled {
+                quarantine(&modules_dir.join(name), &reason);
+            }
+            Bisection { suspects, disabled }.save()?;
+        }
+    }
+    Ok(())
+}
+
+/// Active modules the journal saw change since the last completed boot
+///
+/// Without a completed boot only the latest change is suspected.
+fn changed_modules(active: &[PathBuf]) -> Vec<&PathBuf> {
+    let changes = module_journal::last_changes();
+    let with_change: Vec<(&PathBuf, u64)> = active
+        .iter()
+        .filter_map(|module| {
+            let id = Module::load(module)
+                .map(|module| module.id)
+                .unwrap_or_else(|_| {
+                    module
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string()
+                });
+            Some((module, *changes.get(&id)?))
+        })
+        .collect();
+    match last_good_boot() {
+        Some(last_good) => with_change
+            .into_iter()
+            .filter(|(_, changed)| *changed >= last_good)
+            .map(|(module, _)| module)
+            .collect(),
+        None => with_change
+            .into_iter()
+            .max_by_key(|(_, changed)| *changed)
+            .map(|(module, _)| module)
+            .into_iter()
+            .collect(),
+    }
+}
+
+fn quarantine(module: &Path, reason: &str) {
+    warn!("quarantine module {}: {reason}", module.display());
+    if let Err(e) = fs::write(module.join(defs::QUARANTINE_FILE_NAME), reason) {
+        warn!("Failed to record quarantine of {}: {e}", module.display());
+    }
+    if let Err(e) = ensure_file_exists(module.join(defs::DISABLE_FILE_NAME)) {
+        warn!("Failed to disable {}: {e}", module.display());
+    }
+}
+
+/// Enable a module quarantined by an earlier round again
+fn release(module: &Path) {
+    if read_quarantine(module).is_none() {
+        return;
+    }
+    info!(
+        "release {} from quarantine, it proved innocent",
+        module.display()
+    );
+    let _ = fs::remove_file(module.join(defs::QUARANTINE_FILE_NAME));
+    if let Err(e) = fs::remove_file(module.join(defs::DISABLE_FILE_NAME)) {
+        warn!("Failed to enable {}: {e}", module.display());
+    }
+}
+
+/// The reason a module was quarantined, if it was
+pub fn read_quarantine(module: &Path) -> Option<String> {
+    fs::read_to_string(module.join(defs::QUARANTINE_FILE_NAME))
+        .ok()
+        .map(|reason| reason.trim().to_string())
+}
+
+/// Release a module from quarantine, it is about to be enabled again
+pub fn clear_quarantine(module: &Path) {
+    let path = module.join(defs::QUARANTINE_FILE_NAME);
+    if path.exists() {
+        info!("release {} from quarantine", module.display());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,152 @@
+//! Module zip signatures
+//!
+//! A signed module comes with a detached `<zip>.sig` next to the zip, holding the hex encoded
+//! Ed25519 signature of the SHA-256 digest of the whole zip. Trusted publisher keys are kept in
+//! `trusted_keys/` under the working dir as `<signer>.pub` files with a hex encoded public key.
+//! The signature policy decides whether unsigned or untrusted zips may be installed.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use ed25519_dalek::{Signature, VerifyingKey};
+use log::warn;
+use sha2::{Digest, Sha256};
+
+use crate::defs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Refuse zips which are not signed by a trusted key
+    Require,
+    /// Install unsigned zips after printing a warning
+    Warn,
+    /// Install anything silently
+    Allow,
+}
+
+impl SignaturePolicy {
+    pub fn load() -> Self {
+        let Ok(content) = fs::read_to_string(defs::MODULE_SIGNATURE_POLICY_PATH) else {
+            return Self::Warn;
+        };
+        match content.trim() {
+            "require" => Self::Require,
+            "allow" => Self::Allow,
+            "warn" => Self::Warn,
+            other => {
+                warn!("Unknown module signature policy '{other}', using warn");
+                Self::Warn
+            }
+        }
+    }
+}
+
+pub enum SignatureStatus {
+    /// Signed by the trusted key of this signer
+    Trusted(String),
+    /// No `<zip>.sig` next to the zip
+    Unsigned,
+    /// A signature exists but no trusted key verifies it
+    Untrusted,
+}
+
+pub fn signature_path(zip: &Path) -> PathBuf {
+    let mut path = zip.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+fn decode_hex<const N: usize>(content: &str) -> Option<[u8; N]> {
+    hex::decode(content.trim()).ok()?.try_into().ok()
+}
+
+fn trusted_keys() -> Vec<(String, VerifyingKey)> {
+    let Ok(dir) = fs::read_dir(defs::MODULE_TRUSTED_KEYS_DIR) else {
+        return Vec::new();
+    };
+    let mut keys = Vec::new();
+    for entry in dir.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "pub") {
+            continue;
+        }
+        let Some(signer) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let key = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| decode_hex(&content))
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+        match key {
+            Some(key) => keys.push((signer.to_string(), key)),
+            None => warn!("Ignoring invalid trusted key {}", path.display()),
+        }
+    }
+    keys
+}
+
+pub fn zip_digest(zip: &Path) -> Result<[u8; 32]> {
+    let mut file = fs::File::open(zip)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Check the detached signature of `zip` against the trusted keys
+pub fn signature_status(zip: &Path) -> Result<SignatureStatus> {
+    let content = match fs::read_to_string(signature_path(zip)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(SignatureStatus::Unsigned),
+        Err(e) => return Err(e).context("Failed to read module signature"),
+    };
+    let Some(signature) = decode_hex(&content).map(|bytes| Signature::from_bytes(&bytes)) else {
+        bail!("Malformed module signature, expected 64 hex encoded bytes");
+    };
+
+    let digest = zip_digest(zip)?;
+    Ok(trusted_keys()
+        .into_iter()
+        .find(|(_, key)| key.verify_strict(&digest, &signature).is_ok())
+        .map_or(SignatureStatus::Untrusted, |(signer, _)| {
+            SignatureStatus::Trusted(signer)
+        }))
+}
+
+/// Apply the signature policy to `zip`, returning the trusted signer if there is one
+pub fn check_signature(zip: &Path) -> Result<Option<String>> {
+    let policy = SignaturePolicy::load();
+    let problem = match signature_status(zip)? {
+        SignatureStatus::Trusted(signer) => {
+            println!("- Signed by {signer}");
+            return Ok(Some(signer));
+        }
+        SignatureStatus::Unsigned => "Module zip is not signed",
+        SignatureStatus::Untrusted => "Module zip is not signed by a trusted key",
+    };
+    match policy {
+        SignaturePolicy::Require => bail!("{problem}, refusing to install"),
+        SignaturePolicy::Warn => println!("! {problem}"),
+        SignaturePolicy::Allow => {}
+    }
+    Ok(None)
+}
+
+/// Record the signer of an installed module in `module_dir`, removing any stale record
+pub fn record_signer(module_dir: &Path, signer: Option<&str>) -> Result<()> {
+    let path = module_dir.join(defs::SIGNER_FILE_NAME);
+    match signer {
+        Some(signer) => fs::write(&path, signer)?,
+        None if path.exists() => fs::remove_file(&path)?,
+        None => {}
+    }
+    Ok(())
+}
+
+pub fn read_signer(module_dir: &Path) -> Option<String> {
+    fs::read_to_string(module_dir.join(defs::SIGNER_FILE_NAME))
+        .ok()
+        .map(|signer| signer.trim().to_string())
+}
@@ -0,0 +1,118 @@
+//! Per-module status store
+//!
+//! Results of module scripts are kept in `module_status/<id>.json` under the working dir, outside
+//! the module dir so they survive module updates. Each stage keeps only its latest result.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    os::fd::AsRawFd,
+    path::PathBuf,
+    process::ExitStatus,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{defs, utils::ensure_dir_exists};
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ScriptResult {
+    /// Exit code, `None` if the script was killed by a signal
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// The script ran past its timeout and its process group was killed
+    pub timed_out: bool,
+    pub duration_ms: u64,
+    /// Seconds since the epoch when the script finished
+    pub finished_at: u64,
+    /// Why the script could not be started, it never ran if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ScriptResult {
+    pub fn new(status: ExitStatus, timed_out: bool, duration: Duration) -> Self {
+        use std::os::unix::process::ExitStatusExt;
+        Self {
+            exit_code: status.code(),
+            signal: status.signal(),
+            timed_out,
+            duration_ms: duration.as_millis() as u64,
+            finished_at: now_secs(),
+            error: None,
+        }
+    }
+
+    /// Result of a script which could not be started
+    pub fn failed_to_start(error: String) -> Self {
+        Self {
+            finished_at: now_secs(),
+            error: Some(error),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ModuleStatus {
+    /// Latest script result by stage name, e.g. `post-fs-data`
+    pub scripts: BTreeMap<String, ScriptResult>,
+}
+
+fn status_path(id: &str) -> PathBuf {
+    PathBuf::from(defs::MODULE_STATUS_DIR).join(format!("{id}.json"))
+}
+
+impl ModuleStatus {
+    pub fn load(id: &str) -> Result<Self> {
+        match fs::read_to_string(status_path(id)) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse status of {id}")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read status of {id}")),
+        }
+    }
+
+    pub fn save(&self, id: &str) -> Result<()> {
+        ensure_dir_exists(defs::MODULE_STATUS_DIR)?;
+        let path = status_path(id);
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Record the result of the `stage` script of module `id`
+pub fn record_script(id: &str, stage: &str, result: ScriptResult) {
+    let update = || -> Result<()> {
+        ensure_dir_exists(defs::MODULE_STATUS_DIR)?;
+        // schedulers of overlapping stages may record for the same module at once
+        let dir = fs::File::open(defs::MODULE_STATUS_DIR)?;
+        if unsafe { libc::flock(dir.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut status = ModuleStatus::load(id).unwrap_or_default();
+        status.scripts.insert(stage.to_string(), result);
+        status.save(id)
+    };
+    if let Err(e) = update() {
+        warn!("Failed to record {stage} result of {id}: {e}");
+    }
+}
+
+/// Remove the status of a module which is gone
+pub fn clear_status(id: &str) {
+    let _ = fs::remove_file(status_path(id));
+}
@@ -0,0 +1,250 @@
+//! Transactional module installation
+//!
+//! Installing a module touches the staged update in `modules_update/<id>`, the flag files and
+//! module.prop in `modules/<id>`, the module config dir and the metamodule symlink. The rest of
+//! `modules/<id>` is left alone until the update is applied at boot. Their state is
+//! saved to a backup dir before anything is changed and put back if the install fails. The backup
+//! dir only goes away when the install commits, so an install interrupted by a crash or kill is
+//! rolled back by the next install of the same module.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{defs, metamodule, module_config, utils::ensure_dir_exists};
+
+const STATE_FILE_NAME: &str = "state.json";
+const SAVED_MODULE_FILES: [&str; 5] = [
+    "module.prop",
+    defs::SIGNER_FILE_NAME,
+    defs::DISABLE_FILE_NAME,
+    defs::UPDATE_FILE_NAME,
+    defs::REMOVE_FILE_NAME,
+];
+
+#[derive(Deserialize, Serialize, Default)]
+struct Snapshot {
+    module_existed: bool,
+    update_existed: bool,
+    config_existed: bool,
+    metamodule_link: Option<PathBuf>,
+}
+
+pub struct InstallTransaction {
+    id: String,
+    backup_dir: PathBuf,
+    snapshot: Snapshot,
+}
+
+fn metamodule_link_path() -> &'static Path {
+    Path::new(defs::METAMODULE_DIR.trim_end_matches('/'))
+}
+
+/// Copy a file, or recreate a symlink as a link instead of copying whatever it points to
+fn copy_entry(src: &Path, dst: &Path) -> Result<()> {
+    if fs::symlink_metadata(src)?.is_symlink() {
+        symlink(fs::read_link(src)?, dst)?;
+    } else {
+        fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+
+fn copy_dir(src: &Path, dst: &Path) -> Result<()> {
+    ensure_dir_exists(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            copy_entry(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy `src` to `dst` through a temporary dir, so `dst` is either complete or missing
+fn copy_dir_atomic(src: &Path, dst: &Path, files: Option<&[&str]>) -> Result<()> {
+    let temp = dst.with_extension("tmp");
+    if temp.exists() {
+        fs::remove_dir_all(&temp)?;
+    }
+    match files {
+        Some(files) => {
+            ensure_dir_exists(&temp)?;
+            for name in files {
+                let path = src.join(name);
+                if path.symlink_metadata().is_ok() {
+                    copy_entry(&path, &temp.join(name))?;
+                }
+            }
+        }
+        None => copy_dir(src, &temp)?,
+    }
+    fs::rename(&temp, dst)?;
+    Ok(())
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_dir_all(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+impl InstallTransaction {
+    fn module_dir(&self) -> PathBuf {
+        Path::new(defs::MODULE_DIR).join(&self.id)
+    }
+
+    fn update_dir(&self) -> PathBuf {
+        Path::new(defs::MODULE_UPDATE_DIR).join(&self.id)
+    }
+
+    fn config_dir(&self) -> PathBuf {
+        module_config::get_config_dir(&self.id)
+    }
+
+    /// Save the current state of module `id`, rolling back an unfinished earlier install first
+    pub fn begin(id: &str) -> Result<Self> {
+        let backup_dir = Path::new(defs::MODULE_INSTALL_BACKUP_DIR).join(id);
+        if backup_dir.join(STATE_FILE_NAME).exists() {
+            warn!("Found unfinished install of {id}, rolling it back");
+            let content = fs::read_to_string(backup_dir.join(STATE_FILE_NAME))?;
+            // guessing the state could delete a module which was installed before
+            let snapshot = serde_json::from_str(&content).with_context(|| {
+                format!(
+                    "Corrupt install state in {}, inspect and remove it manually",
+                    backup_dir.display()
+                )
+            })?;
+            let unfinished = Self {
+                id: id.to_string(),
+                backup_dir: backup_dir.clone(),
+                snapshot,
+            };
+            unfinished
+                .rollback()
+                .context("Failed to roll back unfinished install")?;
+        }
+        remove_dir_if_exists(&backup_dir)?;
+        ensure_dir_exists(&backup_dir).context("Failed to create install backup dir")?;
+
+        let mut transaction = Self {
+            id: id.to_string(),
+            backup_dir,
+            snapshot: Snapshot::default(),
+        };
+        transaction.snapshot = Snapshot {
+            module_existed: transaction.module_dir().exists(),
+            update_existed: transaction.update_dir().exists(),
+            config_existed: transaction.config_dir().exists(),
+            metamodule_link: fs::read_link(metamodule_link_path()).ok(),
+        };
+        // the state is written first, rollback only restores the parts that were saved
+        let state_path = transaction.backup_dir.join(STATE_FILE_NAME);
+        let temp_path = state_path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(serde_json::to_string(&transaction.snapshot)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &state_path).context("Failed to save install state")?;
+
+        if transaction.snapshot.module_existed {
+            copy_dir_atomic(
+                &transaction.module_dir(),
+                &transaction.backup_dir.join("module"),
+                Some(&SAVED_MODULE_FILES),
+            )
+            .context("Failed to save module state")?;
+        }
+        if transaction.snapshot.config_existed {
+            copy_dir_atomic(
+                &transaction.config_dir(),
+                &transaction.backup_dir.join("config"),
+                None,
+            )
+            .context("Failed to save module config")?;
+        }
+        if transaction.snapshot.update_existed {
+            fs::rename(
+                transaction.update_dir(),
+                transaction.backup_dir.join("update"),
+            )
+            .context("Failed to save pending module update")?;
+        }
+
+        info!("Started install transaction for {id}");
+        Ok(transaction)
+    }
+
+    /// Make the install permanent by dropping the saved state
+    pub fn commit(self) -> Result<()> {
+        remove_dir_if_exists(&self.backup_dir)?;
+        info!("Committed install transaction for {}", self.id);
+        Ok(())
+    }
+
+    /// Restore module `id` to the state saved by `begin`
+    pub fn rollback(self) -> Result<()> {
+        info!("Rolling back install transaction for {}", self.id);
+
+        let update_dir = self.update_dir();
+        let saved_update = self.backup_dir.join("update");
+        if saved_update.exists() {
+            remove_dir_if_exists(&update_dir)?;
+            fs::rename(&saved_update, &update_dir)
+                .context("Failed to restore pending module update")?;
+        } else if !self.snapshot.update_existed {
+            remove_dir_if_exists(&update_dir)?;
+        }
+
+        let module_dir = self.module_dir();
+        let saved_module = self.backup_dir.join("module");
+        if !self.snapshot.module_existed {
+            remove_dir_if_exists(&module_dir)?;
+        } else if saved_module.exists() {
+            for name in SAVED_MODULE_FILES {
+                let saved = saved_module.join(name);
+                let path = module_dir.join(name);
+                // never write through a link the failed install may have left behind
+                if path.symlink_metadata().is_ok() {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to remove {}", path.display()))?;
+                }
+                if saved.symlink_metadata().is_ok() {
+                    copy_entry(&saved, &path)
+                        .with_context(|| format!("Failed to restore {}", path.display()))?;
+                }
+            }
+        }
+
+        let config_dir = self.config_dir();
+        let saved_config = self.backup_dir.join("config");
+        if saved_config.exists() {
+            remove_dir_if_exists(&config_dir)?;
+            copy_dir(&saved_config, &config_dir).context("Failed to restore module config")?;
+        } else if !self.snapshot.config_existed {
+            remove_dir_if_exists(&config_dir)?;
+        }
+
+        let link = metamodule_link_path();
+        let current_link = fs::read_link(link).ok();
+        if current_link != self.snapshot.metamodule_link {
+            match &self.snapshot.metamodule_link {
+                Some(target) => metamodule::ensure_symlink(target)?,
+                None => metamodule::remove_symlink()?,
+            }
+        }
+
+        remove_dir_if_exists(&self.backup_dir)?;
+        Ok(())
+    }
+}
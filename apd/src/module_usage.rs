@@ -0,0 +1,122 @@
+//! Disk usage of modules for `apd module du`
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use log::warn;
+
+use crate::{defs, module_config, module_prop::Module, script_log, utils::dir_size};
+
+/// Ids with a config dir but not a module behind them, `internal.*` configs belong to apd
+fn orphaned_configs(installed: &BTreeSet<&String>) -> Vec<String> {
+    let Ok(dir) = fs::read_dir(defs::MODULE_CONFIG_DIR) else {
+        return Vec::new();
+    };
+    let mut orphans: Vec<String> = dir
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|id| !id.starts_with("internal.") && !installed.contains(id))
+        .collect();
+    orphans.sort();
+    orphans
+}
+
+/// Module dirs in `dir` by the id their module.prop declares
+///
+/// The dir name stands in for the id when module.prop cannot be read, so the space still shows.
+fn module_dirs(dir: &str) -> Vec<(String, PathBuf)> {
+    let Ok(dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    dir.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter_map(|path| {
+            let id = match Module::load(&path) {
+                Ok(module) => module.id,
+                Err(_) => path.file_name()?.to_str()?.to_string(),
+            };
+            Some((id, path))
+        })
+        .collect()
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "K", "M", "G"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes}B")
+    } else {
+        format!("{size:.1}{}", UNITS[unit])
+    }
+}
+
+/// Print the disk usage of every module, removing orphaned configs with `clean`
+pub fn print_usage(clean: bool) -> Result<()> {
+    // module and update dir of every id
+    let mut modules: BTreeMap<String, [Option<PathBuf>; 2]> = BTreeMap::new();
+    for (index, dir) in [defs::MODULE_DIR, defs::MODULE_UPDATE_DIR]
+        .iter()
+        .enumerate()
+    {
+        for (id, path) in module_dirs(dir) {
+            modules.entry(id).or_default()[index] = Some(path);
+        }
+    }
+
+    let size_of = |path: &Option<PathBuf>| path.as_ref().map_or(0, dir_size);
+    let mut rows: Vec<(String, [u64; 4])> = modules
+        .iter()
+        .map(|(id, [module, update])| {
+            let sizes = [
+                size_of(module),
+                size_of(update),
+                dir_size(module_config::get_config_dir(id)),
+                dir_size(script_log::module_log_dir(id)),
+            ];
+            (id.clone(), sizes)
+        })
+        .collect();
+    rows.sort_by_key(|(_, sizes)| std::cmp::Reverse(sizes.iter().sum::<u64>()));
+
+    println!(
+        "{:>9} {:>9} {:>9} {:>9} {:>9}  id",
+        "total", "module", "update", "config", "logs"
+    );
+    for (id, sizes) in &rows {
+        let [module, update, config, logs] = sizes.map(human_size);
+        let total = human_size(sizes.iter().sum());
+        println!("{total:>9} {module:>9} {update:>9} {config:>9} {logs:>9}  {id}");
+    }
+
+    let orphans = orphaned_configs(&modules.keys().collect());
+    if orphans.is_empty() {
+        return Ok(());
+    }
+    println!();
+    for id in &orphans {
+        let size = human_size(dir_size(module_config::get_config_dir(id)));
+        if clean {
+            match module_config::clear_module_configs(id) {
+                Ok(()) => println!("- Removed orphaned config of {id} ({size})"),
+                Err(e) => warn!("Failed to remove orphaned config of {id}: {e}"),
+            }
+        } else {
+            println!("! Orphaned config of {id} ({size})");
+        }
+    }
+    if !clean {
+        println!("Run with --clean to remove orphaned configs");
+    }
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::{self, File},
     io::{self, BufRead},
     path::Path,
@@ -7,9 +7,15 @@ use std::{
     time::Duration,
 };
 
+use anyhow::{Context, Result as AnyResult, bail};
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    package_policy::{self, PackageFacts, Policy},
+    supercall,
+};
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct PackageConfig {
     pub pkg: String,
@@ -136,29 +142,105 @@ where
     File::open(filename).map(|file| io::BufReader::new(file).lines())
 }
 
+/// One line of `/data/system/packages.list`
+///
+/// Fields, in order: package name, uid, debuggable flag, data dir, seinfo, gids,
+/// profileable-from-shell flag, long version code, profileable flag and installer.
+/// Older Android releases omit trailing fields, these default to empty values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageRecord {
+    pub pkg: String,
+    pub uid: i32,
+    pub debuggable: bool,
+    pub data_dir: String,
+    pub seinfo: String,
+    pub gids: Vec<u32>,
+    pub profileable_from_shell: bool,
+    pub version_code: i64,
+    pub profileable: bool,
+    /// Installer package name, or one of `@system`, `@product`, `@null`
+    pub installer: String,
+}
+
+impl PackageRecord {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let pkg = parts.next()?.to_string();
+        let uid = parts.next()?.parse::<i32>().ok()?;
+        let mut next = || parts.next().unwrap_or_default();
+        Some(Self {
+            pkg,
+            uid,
+            debuggable: next() == "1",
+            data_dir: next().to_string(),
+            seinfo: next().to_string(),
+            gids: next()
+                .split(',')
+                .filter_map(|gid| gid.parse().ok())
+                .collect(),
+            profileable_from_shell: next() == "1",
+            version_code: next().parse().unwrap_or_default(),
+            profileable: next() == "1",
+            installer: next().to_string(),
+        })
+    }
+
+    pub fn is_system_app(&self) -> bool {
+        self.installer == "@system"
+    }
+
+    /// The package which installed this one, if it was installed by a package
+    pub fn installer_package(&self) -> Option<&str> {
+        (!self.installer.is_empty() && !self.installer.starts_with('@'))
+            .then_some(self.installer.as_str())
+    }
+}
+
+pub fn read_packages_list() -> io::Result<Vec<PackageRecord>> {
+    Ok(read_lines("/data/system/packages.list")?
+        .map_while(Result::ok)
+        .filter_map(|line| PackageRecord::parse(&line))
+        .collect())
+}
+
+/// Group packages by uid, keeping only uids shared by more than one package
+pub fn shared_uid_packages(records: &[PackageRecord]) -> HashMap<i32, Vec<&str>> {
+    let mut by_uid: HashMap<i32, Vec<&str>> = HashMap::new();
+    for record in records {
+        by_uid.entry(record.uid).or_default().push(&record.pkg);
+    }
+    by_uid.retain(|_, packages| packages.len() > 1);
+    by_uid
+}
+
+/// Warning shown when granting root to `pkg` implicitly grants it to the packages sharing its uid
+pub fn shared_uid_grant_warning(
+    shared: &HashMap<i32, Vec<&str>>,
+    pkg: &str,
+    uid: i32,
+) -> Option<String> {
+    let packages = shared.get(&(uid % 100000))?;
+    let others: Vec<&str> = packages.iter().copied().filter(|p| *p != pkg).collect();
+    (!others.is_empty()).then(|| {
+        format!(
+            "Granting root to {pkg} also grants it to {} sharing uid {uid}",
+            others.join(", ")
+        )
+    })
+}
+
 pub fn synchronize_package_config() -> io::Result<Vec<PackageConfig>> {
     info!("[synchronize_package_uid] Start synchronizing root list with system packages...");
 
     let max_retry = 5;
     for _ in 0..max_retry {
-        match read_lines("/data/system/packages.list") {
-            Ok(lines) => {
-                let system_packages_map: HashSet<(String, String, bool)> = lines
-                    .map_while(Result::ok)
-                    .filter_map(|line| {
-                        let mut parts = line.split_whitespace();
-                        let pkg = parts.next()?.to_string();
-                        let uid = parts.next()?.to_string();
-                        let is_system_app = parts.last()? == "@system";
-                        Some((pkg, uid, is_system_app))
-                    })
-                    .collect();
-
+        match read_packages_list() {
+            Ok(system_packages_list) => {
                 let mut package_configs = read_ap_package_config();
 
-                let system_packages: HashSet<String> = system_packages_map
+                let system_packages: HashSet<String> = system_packages_list
                     .iter()
-                    .map(|(pkg, _, _)| pkg.clone())
+                    .map(|record| record.pkg.clone())
                     .collect();
 
                 let original_len = package_configs.len();
@@ -183,7 +265,15 @@ pub fn synchronize_package_config() -> io::Result<Vec<PackageConfig>> {
                         .map(|c| (c.pkg.clone(), c))
                         .collect();
 
-                let whitelist_mode = whitelist_mode();
+                let policy = match Policy::load() {
+                    Ok(policy) => Some(policy),
+                    Err(e) => {
+                        warn!("Failed to load package policy, new packages are ignored: {e:?}");
+                        None
+                    }
+                };
+                let shared_uids = shared_uid_packages(&system_packages_list);
+
                 let mut extra_configs = Vec::new();
                 let manager_package_id = manager_package_id();
                 let manager_package_id = if system_packages.contains(&manager_package_id) {
@@ -192,35 +282,31 @@ pub fn synchronize_package_config() -> io::Result<Vec<PackageConfig>> {
                     "com.bmax.apatch".to_string()
                 };
 
-                for (pkg, uid, is_system_app) in system_packages_map {
-                    if let Ok(uid) = uid.parse::<i32>() {
-                        if let Some(config) = config_map.get_mut(&pkg) {
-                            if config.uid % 100000 != uid % 100000 {
-                                let new_uid = config.uid / 100000 * 100000 + uid % 100000;
-                                info!(
-                                    "Updating uid for package {}: {} -> {}",
-                                    pkg, config.uid, new_uid
-                                );
-                                config.uid = new_uid;
-                                updated = true;
-                            }
-                        } else if manager_package_id != pkg
-                            && match whitelist_mode {
-                                -1 => false,
-                                0 => !is_system_app,
-                                1 => is_system_app,
-                                2 => true,
-                                _ => false,
-                            }
+                for record in &system_packages_list {
+                    let (pkg, uid) = (&record.pkg, record.uid);
+                    if let Some(config) = config_map.get_mut(pkg) {
+                        if config.uid % 100000 != uid % 100000 {
+                            let new_uid = config.uid / 100000 * 100000 + uid % 100000;
+                            info!(
+                                "Updating uid for package {}: {} -> {}",
+                                pkg, config.uid, new_uid
+                            );
+                            config.uid = new_uid;
+                            updated = true;
+                        }
+                    } else if manager_package_id != *pkg
+                        && let Some(policy) = &policy
+                    {
+                        let facts = PackageFacts {
+                            pkg,
+                            is_system: record.is_system_app(),
+                            installer: record.installer_package(),
+                            shared_uid: shared_uids.contains_key(&uid),
+                        };
+                        if let Some((_, rule)) = policy.evaluate(&facts)
+                            && let Some(config) =
+                                package_policy::apply_action(&rule.action, pkg, uid)
                         {
-                            let config = PackageConfig {
-                                pkg,
-                                exclude: 1,
-                                allow: 0,
-                                uid,
-                                to_uid: 0,
-                                sctx: "u:r:untrusted_app:s0".to_string(),
-                            };
                             extra_configs.push(config);
                         }
                     }
@@ -239,3 +325,102 @@ pub fn synchronize_package_config() -> io::Result<Vec<PackageConfig>> {
     }
     Err(io::Error::other("Failed after max retries"))
 }
+
+const EXPORT_VERSION: u32 = 1;
+
+/// Root and exclude decision of a package, independent of the uid it has on a device
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PackageDecision {
+    pub pkg: String,
+    pub exclude: i32,
+    pub allow: i32,
+    pub to_uid: i32,
+    pub sctx: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackageExport {
+    pub version: u32,
+    pub packages: Vec<PackageDecision>,
+}
+
+/// Print all root and exclude decisions as JSON
+pub fn export_package_config() -> AnyResult<()> {
+    let export = PackageExport {
+        version: EXPORT_VERSION,
+        packages: read_ap_package_config()
+            .into_iter()
+            .map(|config| PackageDecision {
+                pkg: config.pkg,
+                exclude: config.exclude,
+                allow: config.allow,
+                to_uid: config.to_uid,
+                sctx: config.sctx,
+            })
+            .collect(),
+    };
+    println!("{}", serde_json::to_string_pretty(&export)?);
+    Ok(())
+}
+
+/// Import decisions exported by `export_package_config`, matching packages by name
+pub fn import_package_config(path: &str, superkey: &Option<String>) -> AnyResult<()> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+    let export: PackageExport =
+        serde_json::from_str(&content).with_context(|| format!("Failed to parse {path}"))?;
+    if export.version > EXPORT_VERSION {
+        bail!("Unsupported export version {}", export.version);
+    }
+    // without the key the kernel state cannot follow, so leave package_config alone too
+    let Some(key) = supercall::convert_superkey(superkey) else {
+        bail!("A super key is required to apply root and exclude decisions");
+    };
+
+    let packages = read_packages_list().context("Failed to read packages.list")?;
+    let shared_uids = shared_uid_packages(&packages);
+    let installed: HashMap<&str, i32> = packages
+        .iter()
+        .map(|record| (record.pkg.as_str(), record.uid))
+        .collect();
+    let mut package_configs = read_ap_package_config();
+    let mut imported = Vec::new();
+    let mut missing = Vec::new();
+
+    for decision in export.packages {
+        let Some(appid) = installed.get(decision.pkg.as_str()) else {
+            missing.push(decision.pkg);
+            continue;
+        };
+        let existing = package_configs.iter_mut().find(|c| c.pkg == decision.pkg);
+        // keep the user of an existing entry, the app id comes from this device
+        let user = existing.as_ref().map_or(0, |c| c.uid / 100000);
+        let config = PackageConfig {
+            pkg: decision.pkg,
+            exclude: decision.exclude,
+            allow: decision.allow,
+            uid: user * 100000 + appid % 100000,
+            to_uid: decision.to_uid,
+            sctx: decision.sctx,
+        };
+        if config.allow == 1
+            && config.exclude == 0
+            && let Some(warning) = shared_uid_grant_warning(&shared_uids, &config.pkg, config.uid)
+        {
+            eprintln!("! {warning}");
+        }
+        match existing {
+            Some(existing) => *existing = config.clone(),
+            None => package_configs.push(config.clone()),
+        }
+        imported.push(config);
+    }
+
+    write_ap_package_config(&package_configs).context("Failed to write package config")?;
+    supercall::apply_package_configs(&key, &imported);
+
+    println!("Imported {} packages", imported.len());
+    if !missing.is_empty() {
+        println!("Not installed, skipped: {}", missing.join(", "));
+    }
+    Ok(())
+}
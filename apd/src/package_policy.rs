@@ -0,0 +1,312 @@
+//! Rule based defaults for newly seen packages
+//!
+//! A policy file selects a named mode, each mode is an ordered list of rules. The first rule
+//! matching a package decides whether it is excluded, allowed (optionally bound to a root
+//! profile template) or left alone. Without a policy file the legacy integer in
+//! `whitelist_config` selects one of the built-in modes.
+
+use std::{collections::HashMap, fs, io};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_profile::{PackageProfile, ProfileConfig},
+    defs,
+    package::{self, PackageConfig},
+};
+
+pub const MODE_NONE: &str = "none";
+pub const MODE_EXCLUDE_USER: &str = "exclude-user";
+pub const MODE_EXCLUDE_SYSTEM: &str = "exclude-system";
+pub const MODE_EXCLUDE_ALL: &str = "exclude-all";
+
+const DEFAULT_SCONTEXT: &str = "u:r:untrusted_app:s0";
+
+fn default_scontext() -> String {
+    DEFAULT_SCONTEXT.to_string()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Hide root and modules from the package
+    Exclude,
+    /// Grant root, optionally applying a root profile template
+    Allow {
+        #[serde(default)]
+        profile: Option<String>,
+        #[serde(default)]
+        to_uid: i32,
+        #[serde(default = "default_scontext")]
+        sctx: String,
+    },
+    /// Leave the package untouched
+    Ignore,
+}
+
+/// Conditions of a rule, all given conditions must hold
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct RuleMatch {
+    /// Glob on the package name, `*` and `?` are supported
+    pub package: Option<String>,
+    pub system: Option<bool>,
+    /// Glob on the installer package name
+    pub installer: Option<String>,
+    /// Whether the package shares its uid with other packages
+    pub shared_uid: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Rule {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "match", default)]
+    pub matcher: RuleMatch,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Mode {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PolicyFile {
+    pub mode: Option<String>,
+    pub modes: HashMap<String, Mode>,
+}
+
+/// What the rules know about a package
+pub struct PackageFacts<'a> {
+    pub pkg: &'a str,
+    pub is_system: bool,
+    pub installer: Option<&'a str>,
+    pub shared_uid: bool,
+}
+
+pub struct Policy {
+    pub mode_name: String,
+    pub mode: Mode,
+}
+
+fn exclude_rule(name: &str, system: Option<bool>) -> Rule {
+    Rule {
+        name: Some(name.to_string()),
+        matcher: RuleMatch {
+            system,
+            ..RuleMatch::default()
+        },
+        action: RuleAction::Exclude,
+    }
+}
+
+fn builtin_mode(name: &str) -> Option<Mode> {
+    let rules = match name {
+        MODE_NONE => Vec::new(),
+        MODE_EXCLUDE_USER => vec![exclude_rule("user apps", Some(false))],
+        MODE_EXCLUDE_SYSTEM => vec![exclude_rule("system apps", Some(true))],
+        MODE_EXCLUDE_ALL => vec![exclude_rule("all apps", None)],
+        _ => return None,
+    };
+    Some(Mode { rules })
+}
+
+/// Map the legacy `whitelist_config` values to built-in modes
+fn legacy_mode_name(mode: i32) -> &'static str {
+    match mode {
+        0 => MODE_EXCLUDE_USER,
+        1 => MODE_EXCLUDE_SYSTEM,
+        2 => MODE_EXCLUDE_ALL,
+        _ => MODE_NONE,
+    }
+}
+
+/// Match `text` against a glob supporting `*` and `?`
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl RuleMatch {
+    fn matches(&self, facts: &PackageFacts) -> bool {
+        self.package
+            .as_deref()
+            .is_none_or(|glob| glob_match(glob, facts.pkg))
+            && self.system.is_none_or(|system| system == facts.is_system)
+            && self.installer.as_deref().is_none_or(|glob| {
+                facts
+                    .installer
+                    .is_some_and(|installer| glob_match(glob, installer))
+            })
+            && self
+                .shared_uid
+                .is_none_or(|shared| shared == facts.shared_uid)
+    }
+}
+
+impl Policy {
+    /// Load the active policy, falling back to the legacy whitelist mode
+    pub fn load() -> Result<Self> {
+        let file = match fs::read_to_string(defs::PACKAGE_POLICY_PATH) {
+            Ok(content) => Some(
+                serde_json::from_str::<PolicyFile>(&content)
+                    .context("Failed to parse package policy")?,
+            ),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context("Failed to read package policy"),
+        };
+
+        let (mode_name, mut modes) = match file {
+            Some(PolicyFile {
+                mode: Some(mode),
+                modes,
+            }) => (mode, modes),
+            Some(PolicyFile { mode: None, modes }) => (
+                legacy_mode_name(package::whitelist_mode()).to_string(),
+                modes,
+            ),
+            None => (
+                legacy_mode_name(package::whitelist_mode()).to_string(),
+                HashMap::new(),
+            ),
+        };
+
+        // modes in the policy file shadow built-in modes of the same name
+        let mode = match modes.remove(&mode_name) {
+            Some(mode) => mode,
+            None => match builtin_mode(&mode_name) {
+                Some(mode) => mode,
+                None => bail!("Unknown package policy mode: {mode_name}"),
+            },
+        };
+        Ok(Self { mode_name, mode })
+    }
+
+    /// Find the first rule matching the package, returning its index
+    pub fn evaluate(&self, facts: &PackageFacts) -> Option<(usize, &Rule)> {
+        self.mode
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matcher.matches(facts))
+    }
+}
+
+/// Create the package config entry for a package matched by `action`
+pub fn apply_action(action: &RuleAction, pkg: &str, uid: i32) -> Option<PackageConfig> {
+    match action {
+        RuleAction::Exclude => Some(PackageConfig {
+            pkg: pkg.to_string(),
+            exclude: 1,
+            allow: 0,
+            uid,
+            to_uid: 0,
+            sctx: DEFAULT_SCONTEXT.to_string(),
+        }),
+        RuleAction::Allow {
+            profile,
+            to_uid,
+            sctx,
+        } => {
+            if let Some(template) = profile
+                && let Err(e) = bind_profile_template(pkg, template)
+            {
+                warn!("Failed to bind root profile {template} to {pkg}: {e}");
+            }
+            Some(PackageConfig {
+                pkg: pkg.to_string(),
+                exclude: 0,
+                allow: 1,
+                uid,
+                to_uid: *to_uid,
+                sctx: sctx.clone(),
+            })
+        }
+        RuleAction::Ignore => None,
+    }
+}
+
+/// Bind a package to a root profile template unless it already has a profile
+fn bind_profile_template(pkg: &str, template: &str) -> Result<()> {
+    let mut config = ProfileConfig::load()?;
+    if config.packages.contains_key(pkg) {
+        return Ok(());
+    }
+    config.packages.insert(
+        pkg.to_string(),
+        PackageProfile {
+            template: Some(template.to_string()),
+            ..PackageProfile::default()
+        },
+    );
+    config.save()
+}
+
+/// Show which rule of the active policy would fire for `pkg`
+pub fn test_package(pkg: &str) -> Result<()> {
+    let policy = Policy::load()?;
+    let packages = package::read_packages_list()?;
+    let Some(record) = packages.iter().find(|record| record.pkg == pkg) else {
+        bail!("Package {pkg} is not installed");
+    };
+    let shared_uids = package::shared_uid_packages(&packages);
+    let shared = shared_uids.get(&record.uid);
+    let facts = PackageFacts {
+        pkg,
+        is_system: record.is_system_app(),
+        installer: record.installer_package(),
+        shared_uid: shared.is_some(),
+    };
+
+    println!("mode: {}", policy.mode_name);
+    println!(
+        "package: {pkg} (uid {}, {})",
+        record.uid,
+        if facts.is_system { "system" } else { "user" },
+    );
+    if let Some(installer) = facts.installer {
+        println!("installer: {installer}");
+    }
+    if let Some(shared) = shared {
+        println!("shared uid with: {}", shared.join(", "));
+    }
+    match policy.evaluate(&facts) {
+        Some((index, rule)) => {
+            println!(
+                "rule: #{index}{}",
+                rule.name
+                    .as_ref()
+                    .map(|name| format!(" ({name})"))
+                    .unwrap_or_default()
+            );
+            println!("action: {}", serde_json::to_string(&rule.action)?);
+        }
+        None => println!("rule: none, package is ignored"),
+    }
+    Ok(())
+}
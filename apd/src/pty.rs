@@ -1,19 +1,23 @@
 use std::{
     ffi::c_int,
-    fs::File,
-    io::{Read, Write, stderr, stdin, stdout},
+    fs::{File, OpenOptions, Permissions},
+    io::{BufRead, BufReader, Read, Write, stderr, stdin, stdout},
     mem::MaybeUninit,
-    os::fd::{AsFd, AsRawFd, OwnedFd, RawFd},
+    os::{
+        fd::{AsFd, AsRawFd, OwnedFd, RawFd},
+        unix::fs::{OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
     process::exit,
-    ptr::null_mut,
-    sync::Mutex,
+    sync::{Arc, Mutex},
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Ok, Result, bail};
+use anyhow::{Context, Ok, Result, bail, ensure};
 use libc::{
-    EINTR, SIG_BLOCK, SIG_UNBLOCK, SIGWINCH, TIOCGWINSZ, TIOCSWINSZ, fork, pthread_sigmask,
-    sigaddset, sigemptyset, sigset_t, sigwait, waitpid, winsize,
+    EINTR, SIGCONT, SIGHUP, SIGINT, SIGTERM, SIGTSTP, SIGWINCH, TIOCGWINSZ, TIOCSWINSZ,
+    WEXITSTATUS, WIFEXITED, WIFSIGNALED, WTERMSIG, fork, kill, pid_t, tcgetpgrp, waitpid, winsize,
 };
 use rustix::{
     fs::{Mode, OFlags, open},
@@ -24,8 +28,13 @@ use rustix::{
     stdio::{dup2_stderr, dup2_stdin, dup2_stdout},
     termios::{OptionalActions, Termios, isatty, tcgetattr, tcsetattr},
 };
+use serde::{Deserialize, Serialize};
+use signal_hook::iterator::Signals;
 
-use crate::{defs::PTS_NAME, utils::get_tmp_path};
+use crate::{
+    defs::{self, PTS_NAME},
+    utils::{ensure_dir_exists, get_tmp_path},
+};
 
 // https://github.com/topjohnwu/Magisk/blob/5627053b7481618adfdf8fa3569b48275589915b/native/src/core/su/pts.cpp
 
@@ -43,31 +52,40 @@ fn get_pty_num<F: AsFd>(fd: F) -> Result<u32> {
 static OLD_STDIN: Mutex<Option<Termios>> = Mutex::new(None);
 
 #[allow(unused)]
-fn watch_sigwinch_async(slave: RawFd) {
-    let mut winch = MaybeUninit::<sigset_t>::uninit();
+fn sync_winsize(slave: RawFd) {
+    let mut w = MaybeUninit::<winsize>::uninit();
     unsafe {
-        sigemptyset(winch.as_mut_ptr());
-        sigaddset(winch.as_mut_ptr(), SIGWINCH);
-        pthread_sigmask(SIG_BLOCK, winch.as_mut_ptr(), null_mut());
-    }
-
-    thread::spawn(move || unsafe {
-        let mut winch = MaybeUninit::<sigset_t>::uninit();
-        sigemptyset(winch.as_mut_ptr());
-        sigaddset(winch.as_mut_ptr(), SIGWINCH);
-        pthread_sigmask(SIG_UNBLOCK, winch.as_mut_ptr(), null_mut());
-        let mut sig: c_int = 0;
-        loop {
-            let mut w = MaybeUninit::<winsize>::uninit();
-            if libc::ioctl(1, TIOCGWINSZ, w.as_mut_ptr()) < 0 {
+        if libc::ioctl(1, TIOCGWINSZ, w.as_mut_ptr()) < 0 {
+            return;
+        }
+        libc::ioctl(slave, TIOCSWINSZ, w.as_mut_ptr());
+    }
+}
+
+/// Forward job control and termination signals received by the relay to the child session,
+/// and keep the window size of the pty in sync with our terminal.
+#[allow(unused)]
+fn forward_signals_async(ptmx: RawFd, pid: pid_t) -> Result<()> {
+    let mut signals = Signals::new([SIGWINCH, SIGINT, SIGTERM, SIGHUP, SIGTSTP, SIGCONT])?;
+
+    sync_winsize(ptmx);
+    thread::spawn(move || {
+        for sig in signals.forever() {
+            if sig == SIGWINCH {
+                sync_winsize(ptmx);
                 continue;
             }
-            libc::ioctl(slave, TIOCSWINSZ, w.as_mut_ptr());
-            if sigwait(winch.as_mut_ptr(), &mut sig) != 0 {
-                break;
+            if sig == SIGCONT {
+                // the terminal may have been reset while we were stopped
+                let _ = reapply_stdin_raw();
             }
+            // prefer the foreground process group of the pty, which may be a job of the shell
+            let pgrp = unsafe { tcgetpgrp(ptmx) };
+            let target = if pgrp > 0 { pgrp } else { pid };
+            unsafe { kill(-target, sig) };
         }
     });
+    Ok(())
 }
 
 #[allow(unused)]
@@ -82,6 +100,18 @@ fn set_stdin_raw() -> rustix::io::Result<()> {
     tcsetattr(stdin(), OptionalActions::Flush, &termios)
 }
 
+#[allow(unused)]
+fn reapply_stdin_raw() -> rustix::io::Result<()> {
+    let guard = OLD_STDIN.lock().unwrap();
+    let Some(mut termios) = guard.clone() else {
+        return rustix::io::Result::Ok(());
+    };
+    drop(guard);
+
+    termios.make_raw();
+    tcsetattr(stdin(), OptionalActions::Flush, &termios)
+}
+
 #[allow(unused)]
 fn restore_stdin() -> Result<()> {
     let mut guard = OLD_STDIN.lock().unwrap();
@@ -93,8 +123,145 @@ fn restore_stdin() -> Result<()> {
     Ok(())
 }
 
+/// Metadata of a recorded root session
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub caller_uid: u32,
+    pub target_uid: u32,
+    pub shell: String,
+    pub command: Option<String>,
+}
+
+/// Session recording is opt-in, enabled when SU_RECORD_FILE contains 1
 #[allow(unused)]
-fn pump<R: Read, W: Write>(mut from: R, mut to: W) {
+pub fn session_recording_enabled() -> bool {
+    std::fs::read_to_string(defs::SU_RECORD_FILE).is_ok_and(|s| s.trim() == "1")
+}
+
+/// Recordings beyond this many sessions are removed, oldest first
+const MAX_KEPT_SESSIONS: usize = 20;
+
+fn session_dir() -> PathBuf {
+    Path::new(defs::APATCH_LOG_FOLDER).join(defs::SU_SESSION_DIR_NAME)
+}
+
+/// The metadata file of a recorded session
+#[derive(Deserialize)]
+struct SessionRecord {
+    id: String,
+    start: u64,
+    #[serde(flatten)]
+    info: SessionInfo,
+}
+
+/// Recorded sessions, oldest first
+fn load_sessions() -> Vec<SessionRecord> {
+    let Result::Ok(dir) = std::fs::read_dir(session_dir()) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<SessionRecord> = dir
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| serde_json::from_slice(&std::fs::read(path).ok()?).ok())
+        .collect();
+    sessions.sort_by_key(|session| session.start);
+    sessions
+}
+
+fn remove_session(dir: &Path, id: &str) {
+    for ext in ["json", "data", "timing"] {
+        let _ = std::fs::remove_file(dir.join(format!("{id}.{ext}")));
+    }
+}
+
+/// Make room for a new recording
+fn prune_sessions(dir: &Path) {
+    let sessions = load_sessions();
+    let keep = MAX_KEPT_SESSIONS - 1;
+    if sessions.len() <= keep {
+        return;
+    }
+    for session in &sessions[..sessions.len() - keep] {
+        remove_session(dir, &session.id);
+    }
+}
+
+/// Print the recorded sessions, newest first
+pub fn list_sessions() -> Result<()> {
+    let dir = session_dir();
+    println!(
+        "{:<24} {:>10} {:>6} {:>6} {:>9}  command",
+        "id", "start", "caller", "target", "size"
+    );
+    for session in load_sessions().iter().rev() {
+        let size = std::fs::metadata(dir.join(format!("{}.data", session.id)))
+            .map(|meta| meta.len())
+            .unwrap_or_default();
+        println!(
+            "{:<24} {:>10} {:>6} {:>6} {:>9}  {}",
+            session.id,
+            session.start,
+            session.info.caller_uid,
+            session.info.target_uid,
+            size,
+            session
+                .info
+                .command
+                .as_deref()
+                .unwrap_or(&session.info.shell)
+        );
+    }
+    Ok(())
+}
+
+/// Records both directions of a pty session, in the advanced `script --log-io` format:
+/// the timing file holds `<I|O> <delay> <length>` lines and the data file holds the raw bytes.
+struct Recorder {
+    inner: Mutex<(File, File, Instant)>,
+}
+
+impl Recorder {
+    fn create(info: &SessionInfo) -> Result<Self> {
+        let dir = session_dir();
+        ensure_dir_exists(&dir)?;
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+        prune_sessions(&dir);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let id = format!("{}-{}", now.as_secs(), std::process::id());
+        let open = |ext: &str| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(dir.join(format!("{id}.{ext}")))
+        };
+
+        let mut meta = serde_json::to_value(info)?;
+        meta["id"] = id.clone().into();
+        meta["start"] = now.as_secs().into();
+        serde_json::to_writer_pretty(open("json")?, &meta)?;
+
+        log::info!("recording root session {id}");
+        Ok(Self {
+            inner: Mutex::new((open("data")?, open("timing")?, Instant::now())),
+        })
+    }
+
+    fn record(&self, kind: char, buf: &[u8]) {
+        let mut guard = self.inner.lock().unwrap();
+        let (data, timing, last) = &mut *guard;
+        let now = Instant::now();
+        let delay = now.duration_since(*last).as_secs_f64();
+        *last = now;
+        let _ = writeln!(timing, "{kind} {delay:.6} {}", buf.len());
+        let _ = data.write_all(buf);
+    }
+}
+
+#[allow(unused)]
+fn pump<R: Read, W: Write>(mut from: R, mut to: W, mut tap: impl FnMut(&[u8])) {
     let mut buf = [0u8; 4096];
     loop {
         match from.read(&mut buf) {
@@ -102,6 +269,7 @@ fn pump<R: Read, W: Write>(mut from: R, mut to: W) {
                 if len == 0 {
                     return;
                 }
+                tap(&buf[0..len]);
                 if to.write_all(&buf[0..len]).is_err() {
                     return;
                 }
@@ -117,63 +285,99 @@ fn pump<R: Read, W: Write>(mut from: R, mut to: W) {
 }
 
 #[allow(unused)]
-fn pump_stdin_async(mut ptmx: File) {
+fn pump_stdin_async(mut ptmx: File, recorder: Option<Arc<Recorder>>) {
     let _ = set_stdin_raw();
 
     thread::spawn(move || {
         let mut stdin = stdin();
-        pump(&mut stdin, &mut ptmx);
+        pump(&mut stdin, &mut ptmx, |buf| {
+            if let Some(recorder) = &recorder {
+                recorder.record('I', buf);
+            }
+        });
     });
 }
 
 #[allow(unused)]
-fn pump_stdout_blocking(mut ptmx: File) {
+fn pump_stdout_blocking(mut ptmx: File, recorder: Option<Arc<Recorder>>) {
     let mut stdout = stdout();
-    pump(&mut ptmx, &mut stdout);
+    pump(&mut ptmx, &mut stdout, |buf| {
+        if let Some(recorder) = &recorder {
+            recorder.record('O', buf);
+        }
+    });
 
     let _ = restore_stdin();
 }
 
+/// Wait for the child and translate its status into a shell-style exit code
 #[allow(unused)]
-fn create_transfer(ptmx: OwnedFd) -> Result<()> {
-    let pid = unsafe { fork() };
-    match pid {
-        d if d < 0 => bail!("fork"),
-        0 => return Ok(()),
-        _ => {}
+fn wait_exit_code(pid: pid_t) -> i32 {
+    let mut status: c_int = 0;
+    loop {
+        let res = unsafe { waitpid(pid, &mut status, 0) };
+        if res == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(EINTR) {
+                continue;
+            }
+            return 1;
+        }
+        break;
     }
 
+    if WIFEXITED(status) {
+        WEXITSTATUS(status)
+    } else if WIFSIGNALED(status) {
+        128 + WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+#[allow(unused)]
+fn relay(pid: pid_t, ptmx: OwnedFd, session: Option<SessionInfo>) -> Result<i32> {
     let ptmx_r = ptmx;
     let ptmx_w = dup(&ptmx_r)?;
 
     let ptmx_r = File::from(ptmx_r);
     let ptmx_w = File::from(ptmx_w);
 
-    watch_sigwinch_async(ptmx_w.as_raw_fd());
-    pump_stdin_async(ptmx_r);
-    pump_stdout_blocking(ptmx_w);
+    // never refuse the shell because of a recording failure
+    let recorder = session.and_then(|info| {
+        Recorder::create(&info)
+            .inspect_err(|e| log::warn!("failed to record root session: {e:?}"))
+            .ok()
+            .map(Arc::new)
+    });
 
-    let mut status: c_int = -1;
+    forward_signals_async(ptmx_w.as_raw_fd(), pid)?;
+    pump_stdin_async(ptmx_r, recorder.clone());
+    pump_stdout_blocking(ptmx_w, recorder);
 
-    unsafe {
-        loop {
-            let res = waitpid(pid, &mut status, 0);
-            if res == -1 {
-                let err = std::io::Error::last_os_error();
-                if err.raw_os_error() == Some(EINTR) {
-                    continue;
-                }
-                break;
-            }
-            break;
-        }
+    Ok(wait_exit_code(pid))
+}
+
+#[allow(unused)]
+fn create_transfer(ptmx: OwnedFd, session: Option<SessionInfo>) -> Result<()> {
+    let pid = unsafe { fork() };
+    match pid {
+        d if d < 0 => bail!("fork"),
+        0 => return Ok(()),
+        _ => {}
     }
 
-    exit(status)
+    let code = relay(pid, ptmx, session).unwrap_or_else(|e| {
+        log::error!("pty relay failed: {e:?}");
+        1
+    });
+
+    let _ = restore_stdin();
+    exit(code)
 }
 
 #[allow(unused)]
-pub fn prepare_pty() -> Result<()> {
+pub fn prepare_pty(session: Option<SessionInfo>) -> Result<()> {
     let tty_in = isatty(stdin());
     let tty_out = isatty(stdout());
     let tty_err = isatty(stderr());
@@ -190,7 +394,7 @@ pub fn prepare_pty() -> Result<()> {
     grantpt(&ptmx_fd)?;
     unlockpt(&ptmx_fd)?;
     let pty_num = get_pty_num(&ptmx_fd)?;
-    create_transfer(ptmx_fd)?;
+    create_transfer(ptmx_fd, session)?;
     setsid()?;
     let pty_fd = open(format!("{pts_path}/{pty_num}"), OFlags::RDWR, Mode::empty())?;
     if tty_in {
@@ -204,3 +408,40 @@ pub fn prepare_pty() -> Result<()> {
     }
     Ok(())
 }
+
+/// Play a recorded session back to stdout with its original timing
+pub fn replay_session(id: &str, speed: f64) -> Result<()> {
+    ensure!(
+        !id.is_empty() && id.chars().all(|c| c.is_ascii_digit() || c == '-'),
+        "Invalid session id: {id}"
+    );
+    ensure!(speed > 0.0, "Speed must be positive");
+
+    let dir = session_dir();
+    let timing = File::open(dir.join(format!("{id}.timing")))
+        .with_context(|| format!("Session {id} not found"))?;
+    let mut data = File::open(dir.join(format!("{id}.data")))?;
+    let mut out = stdout();
+
+    for line in BufReader::new(timing).lines() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        let (Some(kind), Some(delay), Some(len)) = (parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Malformed timing entry: {line}");
+        };
+        let delay: f64 = delay.parse()?;
+        let len: usize = len.parse()?;
+
+        let mut buf = vec![0u8; len];
+        data.read_exact(&mut buf)?;
+        // input is echoed back by the pty, only the output stream is shown
+        if kind != "O" {
+            continue;
+        }
+        thread::sleep(Duration::from_secs_f64(delay / speed));
+        out.write_all(&buf)?;
+        out.flush()?;
+    }
+    Ok(())
+}
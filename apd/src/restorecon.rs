@@ -7,7 +7,7 @@ use anyhow::{Context, Ok};
 use extattr::{Flags as XattrFlags, lsetxattr};
 use jwalk::{Parallelism::Serial, WalkDir};
 
-use crate::defs;
+use crate::{boot_timeline, defs};
 
 pub const SYSTEM_CON: &str = "u:object_r:system_file:s0";
 pub const ADB_CON: &str = "u:object_r:adb_data_file:s0";
@@ -75,6 +75,7 @@ fn restore_syscon_if_unlabeled<P: AsRef<Path>>(dir: P) -> Result<()> {
 }
 
 pub fn restorecon() -> Result<()> {
+    let _span = boot_timeline::span("restorecon");
     lsetfilecon(defs::DAEMON_PATH, ADB_CON)?;
     restore_syscon_if_unlabeled(defs::MODULE_DIR)?;
     Ok(())
@@ -0,0 +1,255 @@
+//! Captured output of module scripts
+//!
+//! stdout and stderr of module scripts go to `modules/<id>/<stage>.log` in the log folder,
+//! framed by a header and a footer with exit status and duration. Scripts write into a pipe read
+//! by a detached `apd script-log` process, which rotates the log to `<stage>.log.1` whenever it
+//! reaches `MAX_LOG_SIZE`, also while the script is still running. The pump lives as long as
+//! anything holds the pipe open, so daemons started from boot scripts keep a valid stdout after
+//! apd exits.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use rustix::pipe::{PipeFlags, pipe_with};
+
+use crate::{
+    defs,
+    utils::{ensure_dir_exists, switch_cgroups},
+};
+
+const MAX_LOG_SIZE: u64 = 256 * 1024;
+const LOG_EXTENSION: &str = "log";
+
+pub fn module_log_dir(id: &str) -> PathBuf {
+    Path::new(defs::APATCH_LOG_FOLDER).join("modules").join(id)
+}
+
+pub fn log_path(id: &str, stage: &str) -> PathBuf {
+    module_log_dir(id).join(format!("{stage}.{LOG_EXTENSION}"))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Move a log which reached `MAX_LOG_SIZE` to `<stage>.log.1`, replacing the older one
+fn rotate(path: &Path) -> io::Result<()> {
+    let mut rotated = path.to_path_buf().into_os_string();
+    rotated.push(".1");
+    fs::rename(path, rotated)
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Copy `from` into the log at `path` until it is closed, rotating the log at `MAX_LOG_SIZE`
+fn pump(mut from: impl Read, path: &Path) -> Result<()> {
+    let mut file =
+        open_append(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut size = file.metadata()?.len();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let len = match from.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if size > 0 && size + len as u64 > MAX_LOG_SIZE {
+            rotate(path)?;
+            file = open_append(path)?;
+            size = 0;
+        }
+        // keep draining even if the log cannot be written, the script must not block on us
+        if file.write_all(&buffer[..len]).is_ok() {
+            size += len as u64;
+        }
+    }
+}
+
+/// Copy stdin into the `stage` log of module `id`
+///
+/// Runs as `apd script-log`, detached from the apd that started the script.
+pub fn pump_log(id: &str, stage: &str) -> Result<()> {
+    ensure_dir_exists(module_log_dir(id))?;
+    pump(io::stdin().lock(), &log_path(id, stage))
+}
+
+pub struct ScriptLog {
+    /// Write end of the pipe to the log pump
+    pipe: File,
+}
+
+impl ScriptLog {
+    /// Start the log pump for the `stage` script of module `id`
+    pub fn open(id: &str, stage: &str, waited: bool) -> Result<Self> {
+        let (reader, writer) = pipe_with(PipeFlags::CLOEXEC)?;
+        let mut command = Command::new(defs::DAEMON_PATH);
+        command.process_group(0);
+        unsafe {
+            command.pre_exec(|| {
+                switch_cgroups();
+                Ok(())
+            });
+        }
+        command
+            .args(["script-log", id, stage])
+            .stdin(Stdio::from(reader))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to start log pump for {stage} of {id}"))?;
+
+        let mut pipe = File::from(writer);
+        writeln!(
+            pipe,
+            "--- {stage} started at {}{} ---",
+            now_secs(),
+            if waited { "" } else { ", not waited for" }
+        )?;
+        Ok(Self { pipe })
+    }
+
+    /// Send stdout and stderr of `command` to the log
+    pub fn redirect(&self, command: &mut Command) -> io::Result<()> {
+        command
+            .stdout(self.pipe.try_clone()?)
+            .stderr(self.pipe.try_clone()?);
+        Ok(())
+    }
+
+    /// Spawn `command` with its output piped into the log and echoed to our own output
+    pub fn spawn_tee(&self, command: &mut Command) -> io::Result<(Child, Vec<JoinHandle<()>>)> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut pumps = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            pumps.push(self.spawn_pump(stdout, io::stdout())?);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            pumps.push(self.spawn_pump(stderr, io::stderr())?);
+        }
+        Ok((child, pumps))
+    }
+
+    fn spawn_pump(
+        &self,
+        mut from: impl Read + Send + 'static,
+        mut echo: impl Write + Send + 'static,
+    ) -> io::Result<JoinHandle<()>> {
+        let mut pipe = self.pipe.try_clone()?;
+        Ok(thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while let Ok(len) = from.read(&mut buffer) {
+                if len == 0 {
+                    break;
+                }
+                let _ = echo.write_all(&buffer[..len]);
+                let _ = echo.flush();
+                let _ = pipe.write_all(&buffer[..len]);
+            }
+        }))
+    }
+
+    /// Write the footer with the outcome of a waited script
+    pub fn finish(&mut self, status: &ExitStatus, timed_out: bool, duration: Duration) {
+        use std::os::unix::process::ExitStatusExt;
+        let outcome = match (status.code(), status.signal()) {
+            _ if timed_out => "timed out and killed".to_string(),
+            (Some(code), _) => format!("exit code {code}"),
+            (None, Some(signal)) => format!("killed by signal {signal}"),
+            (None, None) => "unknown exit status".to_string(),
+        };
+        if let Err(e) = writeln!(
+            self.pipe,
+            "--- {outcome}, took {}ms ---",
+            duration.as_millis()
+        ) {
+            warn!("Failed to finish script log: {e}");
+        }
+    }
+}
+
+/// Run `command` for script `stage` of module `id` to completion with its output logged
+pub fn status_logged(command: &mut Command, id: &str, stage: &str) -> io::Result<ExitStatus> {
+    let mut log = match ScriptLog::open(id, stage, true) {
+        Ok(log) => Some(log),
+        Err(e) => {
+            warn!("Failed to open {stage} log of {id}: {e}");
+            None
+        }
+    };
+    if let Some(log) = &log {
+        log.redirect(command)?;
+    }
+    let start = Instant::now();
+    let status = command.status()?;
+    if let Some(log) = &mut log {
+        log.finish(&status, false, start.elapsed());
+    }
+    Ok(status)
+}
+
+/// Print the logs of module `id`, all stages unless `stage` is given
+pub fn print_logs(id: &str, stage: Option<&str>) -> Result<()> {
+    if let Some(stage) = stage {
+        let path = log_path(id, stage);
+        let Ok(content) = fs::read_to_string(&path) else {
+            bail!("No {stage} log for module {id}");
+        };
+        print!("{content}");
+        return Ok(());
+    }
+
+    let Ok(dir) = fs::read_dir(module_log_dir(id)) else {
+        bail!("No logs for module {id}");
+    };
+    let mut logs: Vec<PathBuf> = dir
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == LOG_EXTENSION))
+        .collect();
+    logs.sort();
+    for path in logs {
+        let stage = path.file_stem().unwrap_or_default().to_string_lossy();
+        println!("==> {stage} <==");
+        print!("{}", fs::read_to_string(&path).unwrap_or_default());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pump_rotates_at_max_size() {
+        let dir = std::env::temp_dir().join(format!("apd-script-log-{}", std::process::id()));
+        ensure_dir_exists(&dir).unwrap();
+        let path = dir.join("service.log");
+        let rotated = dir.join("service.log.1");
+
+        let output = vec![b'x'; MAX_LOG_SIZE as usize * 5 / 2];
+        pump(output.as_slice(), &path).unwrap();
+
+        let size = fs::metadata(&path).unwrap().len();
+        assert!(size > 0 && size <= MAX_LOG_SIZE, "log has {size} bytes");
+        assert_eq!(fs::metadata(&rotated).unwrap().len(), MAX_LOG_SIZE);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,7 +2,9 @@ use anyhow::{Context, Result, bail};
 use clap::Parser;
 use policy::{SePolicy, format_statement_help};
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+pub const LIVE_POLICY_PATH: &str = "/sys/fs/selinux/policy";
 
 /// Write adapter for formatting
 struct WriteAdapter<T>(T);
@@ -114,7 +116,7 @@ pub fn get_policy_main(args: &[String]) -> Result<SePolicy> {
     } else if cli.compile_split {
         SePolicy::compile_split().context("Cannot compile split policy")?
     } else {
-        SePolicy::from_file("/sys/fs/selinux/policy").context("Cannot load live policy")?
+        SePolicy::from_file(LIVE_POLICY_PATH).context("Cannot load live policy")?
     };
     execute_next(&cli, &mut sepol)?;
     Ok(sepol)
@@ -138,7 +140,7 @@ pub fn execute(cli: &Args) -> Result<()> {
     } else if cli.compile_split {
         SePolicy::compile_split().context("Cannot compile split policy")?
     } else {
-        SePolicy::from_file("/sys/fs/selinux/policy").context("Cannot load live policy")?
+        SePolicy::from_file(LIVE_POLICY_PATH).context("Cannot load live policy")?
     };
 
     execute_next(cli, &mut sepol)?;
@@ -215,3 +217,212 @@ it will load from current live policies (/sys/fs/selinux/policy)
     let _ = format_statement_help(&mut WriteAdapter(io::stderr()));
     eprintln!();
 }
+
+/// How an argument of a policy statement may be written
+#[derive(Clone, Copy)]
+enum ArgKind {
+    /// A name, a `{ ... }` set or `*`
+    Any,
+    /// A name or a `{ ... }` set
+    Set,
+    /// A single name
+    Name,
+    /// Must be exactly this keyword
+    Keyword(&'static str),
+    /// An ioctl number, range or set, optionally complemented with `~`
+    Xperm,
+}
+
+/// Required and optional arguments of a statement
+fn statement_args(command: &str) -> Option<(&'static [ArgKind], &'static [ArgKind])> {
+    use ArgKind::{Any, Keyword, Name, Set, Xperm};
+    Some(match command {
+        "allow" | "deny" | "auditallow" | "dontaudit" => (&[Any, Any, Any, Any], &[]),
+        "allowxperm" | "auditallowxperm" | "dontauditxperm" => {
+            (&[Any, Any, Any, Keyword("ioctl"), Xperm], &[])
+        }
+        "permissive" | "enforce" => (&[Set], &[]),
+        "typeattribute" => (&[Set, Set], &[]),
+        "type" => (&[Name], &[Set]),
+        "attribute" => (&[Name], &[]),
+        "type_transition" => (&[Name, Name, Name, Name], &[Name]),
+        "type_change" | "type_member" => (&[Name, Name, Name, Name], &[]),
+        "genfscon" => (&[Name, Name, Name], &[]),
+        _ => return None,
+    })
+}
+
+fn tokenize(statement: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in statement.char_indices() {
+        let is_delimiter = matches!(c, '{' | '}' | '~');
+        if c.is_whitespace() || is_delimiter {
+            if let Some(begin) = start.take() {
+                tokens.push(&statement[begin..index]);
+            }
+            if is_delimiter {
+                tokens.push(&statement[index..index + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(index);
+        }
+    }
+    if let Some(begin) = start {
+        tokens.push(&statement[begin..]);
+    }
+    tokens
+}
+
+fn is_name(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/' | ':'))
+}
+
+fn is_xperm(token: &str) -> bool {
+    let parse = |value: &str| {
+        value
+            .strip_prefix("0x")
+            .is_some_and(|hex| u16::from_str_radix(hex, 16).is_ok())
+    };
+    match token.split_once('-') {
+        Some((low, high)) => parse(low) && parse(high),
+        None => parse(token),
+    }
+}
+
+/// Consume one argument of `kind` from `tokens`
+fn check_arg<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    kind: ArgKind,
+    position: usize,
+) -> Result<()> {
+    let Some(mut token) = tokens.next() else {
+        bail!("missing argument {position}");
+    };
+    if matches!(kind, ArgKind::Xperm) && token == "~" {
+        let Some(next) = tokens.next() else {
+            bail!("missing argument {position} after '~'");
+        };
+        token = next;
+    }
+
+    if token == "{" {
+        if matches!(kind, ArgKind::Name | ArgKind::Keyword(_)) {
+            bail!("argument {position} must be a single name, not a set");
+        }
+        let mut empty = true;
+        loop {
+            match tokens.next() {
+                Some("}") if !empty => return Ok(()),
+                Some("}") => bail!("argument {position} is an empty set"),
+                Some(item) if matches!(kind, ArgKind::Xperm) && is_xperm(item) => empty = false,
+                Some(item) if !matches!(kind, ArgKind::Xperm) && is_name(item) => empty = false,
+                Some(item) => bail!("invalid set element '{item}' in argument {position}"),
+                None => bail!("unterminated set in argument {position}"),
+            }
+        }
+    }
+
+    let valid = match kind {
+        ArgKind::Any => token == "*" || is_name(token),
+        ArgKind::Set | ArgKind::Name => is_name(token),
+        ArgKind::Keyword(keyword) => token == keyword,
+        ArgKind::Xperm => is_xperm(token),
+    };
+    if !valid {
+        bail!("invalid argument {position} '{token}'");
+    }
+    Ok(())
+}
+
+fn check_statement(statement: &str) -> Result<()> {
+    let tokens = tokenize(statement);
+    let mut tokens = tokens.into_iter().peekable();
+    let Some(command) = tokens.next() else {
+        return Ok(());
+    };
+    let Some((required, optional)) = statement_args(command) else {
+        bail!("unknown statement '{command}'");
+    };
+    for (index, kind) in required.iter().enumerate() {
+        check_arg(&mut tokens, *kind, index + 1)?;
+    }
+    for (index, kind) in optional.iter().enumerate() {
+        if tokens.peek().is_none() {
+            break;
+        }
+        check_arg(&mut tokens, *kind, required.len() + index + 1)?;
+    }
+    if let Some(extra) = tokens.next() {
+        bail!("unexpected '{extra}' after the last argument");
+    }
+    Ok(())
+}
+
+/// Check the syntax of policy statements without loading a policy
+///
+/// Statements are separated by newlines or `;`, `#` starts a comment. Whether the types and
+/// classes exist is only known once the statements are applied with [`check_rule`].
+pub fn check_syntax(statements: &str) -> Result<()> {
+    for (index, line) in statements.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for statement in line.split(';') {
+            check_statement(statement)
+                .with_context(|| format!("line {}: '{}'", index + 1, statement.trim()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check the rule file `rule` with the parser that applies it at boot
+///
+/// The rules are applied to `policy`, or the live policy, in memory only. Nothing is written
+/// back or loaded into the kernel.
+pub fn check_rule(rule: &Path, policy: Option<&Path>) -> Result<()> {
+    let args = Args {
+        load: Some(policy.unwrap_or(Path::new(LIVE_POLICY_PATH)).to_path_buf()),
+        load_split: false,
+        compile_split: false,
+        save: None,
+        live: false,
+        magisk: false,
+        apply: vec![rule.to_path_buf()],
+        print_rules: false,
+        policies: Vec::new(),
+    };
+    execute(&args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_statements() {
+        let rules = "# comment\n\
+            allow { untrusted_app shell } * file { read open }\n\
+            allowxperm domain devpts chr_file ioctl ~{ 0x5401-0x5403 0x540b }\n\
+            type_transition init tmpfs file mytmp; permissive mytype\n\
+            genfscon proc /foo u:object_r:proc:s0\n";
+        check_syntax(rules).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_statements() {
+        for rule in [
+            "allow a b c",
+            "alow a b c d",
+            "allow a b c { d",
+            "allow a b c {}",
+            "permissive *",
+            "type_transition { a b } c d e",
+            "allowxperm a b c ioctl 12",
+            "attribute a b",
+        ] {
+            assert!(check_syntax(rule).is_err(), "{rule} was accepted");
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::cli::SUPERCALL;
-use crate::package::synchronize_package_config;
+use crate::package::{
+    PackageConfig, read_packages_list, shared_uid_grant_warning, shared_uid_packages,
+    synchronize_package_config,
+};
 use ap_supercall::su_profile::SuProfile;
 use std::{
     ffi::{CStr, CString},
@@ -29,7 +32,7 @@ fn convert_string_to_u8_array(s: &str) -> [u8; SUPERCALL_SCONTEXT_LEN] {
     u8_array
 }
 
-fn convert_superkey(s: &Option<String>) -> Option<CString> {
+pub fn convert_superkey(s: &Option<String>) -> Option<CString> {
     s.as_ref().and_then(|s| CString::new(s.clone()).ok())
 }
 
@@ -69,44 +72,81 @@ pub fn refresh_ap_package_list(skey: &CStr, mutex: &Arc<Mutex<()>>) {
         }
     }
 
+    let packages = read_packages_list().unwrap_or_default();
+    let shared_uids = shared_uid_packages(&packages);
+
     match synchronize_package_config() {
         Ok(package_configs) => {
             for config in package_configs {
-                if config.allow == 1 && config.exclude == 0 {
-                    let mut profile = SuProfile {
-                        uid: config.uid,
-                        to_uid: config.to_uid,
-                        scontext: convert_string_to_u8_array(&config.sctx),
-                    };
-                    match SUPERCALL.sc_su_grant_uid(skey, &mut profile) {
-                        Ok(result) => info!(
-                            "[refresh_ap_package_list] Loading {}: result = {}",
-                            config.pkg, result
-                        ),
-                        Err(e) => error!(
-                            "[refresh_ap_package_list] Loading {}: result = Err: {:?}",
-                            config.pkg, e
-                        ),
-                    }
-                }
-                if config.allow == 0 && config.exclude == 1 {
-                    match SUPERCALL.sc_set_ap_mod_exclude(skey, config.uid as i64, 1) {
-                        Ok(result) => info!(
-                            "[refresh_ap_package_list] Loading exclude {}: result = {}",
-                            config.pkg, result
-                        ),
-                        Err(e) => error!(
-                            "[refresh_ap_package_list] Loading exclude {}: result = Err: {:?}",
-                            config.pkg, e
-                        ),
-                    }
+                if config.allow == 1
+                    && config.exclude == 0
+                    && let Some(warning) =
+                        shared_uid_grant_warning(&shared_uids, &config.pkg, config.uid)
+                {
+                    warn!("{warning}");
                 }
+                load_package_config(skey, &config);
             }
         }
         Err(e) => error!("Failed to synchronize package UIDs: {}", e),
     }
 }
 
+fn load_package_config(skey: &CStr, config: &PackageConfig) {
+    if config.allow == 1 && config.exclude == 0 {
+        let mut profile = SuProfile {
+            uid: config.uid,
+            to_uid: config.to_uid,
+            scontext: convert_string_to_u8_array(&config.sctx),
+        };
+        match SUPERCALL.sc_su_grant_uid(skey, &mut profile) {
+            Ok(result) => info!(
+                "[refresh_ap_package_list] Loading {}: result = {}",
+                config.pkg, result
+            ),
+            Err(e) => error!(
+                "[refresh_ap_package_list] Loading {}: result = Err: {:?}",
+                config.pkg, e
+            ),
+        }
+    }
+    if config.allow == 0 && config.exclude == 1 {
+        match SUPERCALL.sc_set_ap_mod_exclude(skey, config.uid as i64, 1) {
+            Ok(result) => info!(
+                "[refresh_ap_package_list] Loading exclude {}: result = {}",
+                config.pkg, result
+            ),
+            Err(e) => error!(
+                "[refresh_ap_package_list] Loading exclude {}: result = Err: {:?}",
+                config.pkg, e
+            ),
+        }
+    }
+}
+
+/// Apply imported package configs to the kernel, clearing state the configs no longer grant
+pub fn apply_package_configs(key: &CStr, configs: &[PackageConfig]) {
+    for config in configs {
+        if config.allow == 0
+            && let Err(e) = SUPERCALL.sc_su_revoke_uid(key, config.uid as uid_t)
+        {
+            warn!(
+                "[apply_package_configs] Revoking {}: Err: {:?}",
+                config.pkg, e
+            );
+        }
+        if config.exclude == 0
+            && let Err(e) = SUPERCALL.sc_set_ap_mod_exclude(key, config.uid as i64, 0)
+        {
+            warn!(
+                "[apply_package_configs] Clearing exclude {}: Err: {:?}",
+                config.pkg, e
+            );
+        }
+        load_package_config(key, config);
+    }
+}
+
 pub fn privilege_apd_profile(superkey: &Option<String>) {
     let key = convert_superkey(superkey);
 
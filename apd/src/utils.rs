@@ -111,6 +111,26 @@ pub fn switch_mnt_ns(pid: i32) -> Result<()> {
     Ok(())
 }
 
+/// Move into a private copy of the current mount namespace
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn unshare_mnt_ns() -> Result<()> {
+    use anyhow::ensure;
+    let ret = unsafe { libc::unshare(libc::CLONE_NEWNS) };
+    ensure!(ret == 0, "unshare mnt ns failed");
+    // stop our mounts from propagating back to the namespace we came from
+    let ret = unsafe {
+        libc::mount(
+            c"none".as_ptr(),
+            c"/".as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        )
+    };
+    ensure!(ret == 0, "make mnt ns private failed");
+    Ok(())
+}
+
 fn switch_cgroup(grp: &str, pid: u32) {
     let path = Path::new(grp).join("cgroup.procs");
     if !path.exists() {
@@ -159,3 +179,16 @@ pub fn get_tmp_path() -> &'static str {
     }
     ""
 }
+
+/// Total size in bytes of the files under `path`, not following symlinks
+pub fn dir_size<T: AsRef<Path>>(path: T) -> u64 {
+    let Result::Ok(meta) = std::fs::symlink_metadata(&path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    std::fs::read_dir(&path)
+        .map(|dir| dir.flatten().map(|entry| dir_size(entry.path())).sum())
+        .unwrap_or_default()
+}
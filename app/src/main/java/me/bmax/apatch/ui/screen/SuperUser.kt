@@ -317,6 +317,9 @@ fun SuperUserScreen(setFab: FabProvider) {
                                 if (app.excludeApp == 1) {
                                     LabelText(label = stringResource(id = R.string.su_pkg_excluded_label))
                                 }
+                                if (app.sharedUidPackages.isNotEmpty()) {
+                                    LabelText(label = stringResource(id = R.string.su_shared_uid_label))
+                                }
                                 if (app.config.allow != 0) {
                                     FlowRow {
                                         LabelText(label = app.config.profile.uid.toString())
@@ -348,6 +351,17 @@ fun SuperUserScreen(setFab: FabProvider) {
                                     if (app.config.allow == 1) {
                                         Natives.grantSu(app.uid, 0, app.config.profile.scontext)
                                         Natives.setUidExclude(app.uid, 0)
+                                        if (app.sharedUidPackages.isNotEmpty()) {
+                                            launch {
+                                                snackBarHost.showSnackbar(
+                                                    message = context.getString(
+                                                        R.string.su_shared_uid_grant_warning,
+                                                        app.sharedUidPackages.joinToString(", ")
+                                                    ),
+                                                    duration = SnackbarDuration.Long
+                                                )
+                                            }
+                                        }
                                     } else {
                                         Natives.revokeSu(app.uid)
                                         val mode = getWhiteListMode()
@@ -41,7 +41,9 @@ class SuperUserViewModel : ViewModel() {
         val packageName: String,
         val uid: Int,
         val packageInfo: PackageInfo,
-        val config: PkgConfig.Config
+        val config: PkgConfig.Config,
+        // other packages running as the same uid, a grant covers them too
+        val sharedUidPackages: List<String> = emptyList()
     ) : Parcelable {
         @IgnoredOnParcel
         var rootGranted by mutableStateOf(config.allow != 0)
@@ -141,6 +143,7 @@ class SuperUserViewModel : ViewModel() {
             val uids = Natives.suUids().toList()
             Log.d(TAG, "all allows: $uids")
 
+            val packagesByUid = packages.list.groupBy({ it.applicationInfo!!.uid }, { it.packageName })
             val newApps = packages.list.map { pkg ->
                 val appInfo = pkg.applicationInfo!!
                 val uid = appInfo.uid
@@ -162,7 +165,8 @@ class SuperUserViewModel : ViewModel() {
                     packageInfo = pkg,
                     packageName = packageName,
                     uid = uid,
-                    config = config
+                    config = config,
+                    sharedUidPackages = packagesByUid[uid].orEmpty().filter { it != packageName }
                 )
             }
 